@@ -0,0 +1,483 @@
+use anyhow::Result;
+
+use crate::{
+    lexer::is_name,
+    variable::{Unbound, Variables},
+};
+
+// Longest operators first, so that prefixes match last.
+const OPERATORS: [&str; 41] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^",
+    "|", "?", ":", "(", ")", ",", "[", "]",
+];
+
+const ASSIGNMENT_OPERATORS: [&str; 11] = [
+    "=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|=",
+];
+
+// Binary operators from the loosest to the tightest binding.
+const PRECEDENCE: [&[&str]; 11] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+    &["**"],
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+/// A variable or array element that can be read and assigned.
+#[derive(Debug)]
+struct Lvalue {
+    name: String,
+    subscript: Option<(Box<Expr>, String)>, // the index, and its text as an associative key
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Variable(Lvalue),
+    Unary(&'static str, Box<Expr>),
+    Increment {
+        lvalue: Lvalue,
+        delta: i64,
+        prefix: bool,
+    },
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Assign(Lvalue, &'static str, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+}
+
+/// Evaluates an arithmetic expression as in `(( ))`, with C operators on 64-bit integers.
+pub fn evaluate(expression: &str, variables: &mut Variables) -> Result<i64> {
+    evaluate_nested(expression, variables, 0)
+}
+
+fn evaluate_nested(expression: &str, variables: &mut Variables, depth: usize) -> Result<i64> {
+    anyhow::ensure!(
+        depth < 64,
+        "{}: expression recursion level exceeded",
+        expression
+    );
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = ExprParser { tokens, pos: 0 };
+    let expr = parser.parse_comma()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        anyhow::bail!(
+            "{}: syntax error in expression (error token is \"{}\")",
+            expression.trim(),
+            token_text(token)
+        )
+    }
+    eval(&expr, variables, depth)
+}
+
+fn token_text(token: &Token) -> String {
+    match token {
+        Token::Number(n) => n.to_string(),
+        Token::Name(name) => name.clone(),
+        Token::Operator(op) => op.to_string(),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "_#".contains(chars[i])) {
+                i += 1;
+            }
+            let text: String = chars[start..i].iter().collect();
+            if c.is_ascii_digit() {
+                tokens.push(Token::Number(parse_number(&text)?));
+            } else {
+                tokens.push(Token::Name(text));
+            }
+        } else if let Some(op) = OPERATORS.iter().find(|op| {
+            op.chars()
+                .enumerate()
+                .all(|(j, c)| chars.get(i + j) == Some(&c))
+        }) {
+            tokens.push(Token::Operator(op));
+            i += op.len();
+        } else {
+            anyhow::bail!(
+                "{}: syntax error: operand expected (error token is \"{}\")",
+                expression.trim(),
+                chars[i..].iter().collect::<String>()
+            )
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses decimal, octal (`017`), hexadecimal (`0x1f`) and `base#digits` integers.
+fn parse_number(text: &str) -> Result<i64> {
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        let base = base.parse::<u32>().unwrap_or(0);
+        anyhow::ensure!(
+            (2..=64).contains(&base),
+            "{}: invalid arithmetic base",
+            text
+        );
+        (base, digits)
+    } else if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, hex)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => u32::MAX,
+        };
+        anyhow::ensure!(
+            digit < base,
+            "{}: value too great for base (error token is \"{}\")",
+            text,
+            text
+        );
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    Ok(value)
+}
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Operator(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<()> {
+        anyhow::ensure!(
+            self.peek_operator() == Some(op),
+            "syntax error: `{}' expected",
+            op
+        );
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_assignment()?;
+        while self.peek_operator() == Some(",") {
+            self.pos += 1;
+            expr = Expr::Comma(Box::new(expr), Box::new(self.parse_assignment()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_assignment(&mut self) -> Result<Expr> {
+        if let Some(Token::Name(_)) = self.tokens.get(self.pos) {
+            let start = self.pos;
+            let lvalue = self.parse_lvalue()?;
+            if let Some(op) = self.peek_operator()
+                && ASSIGNMENT_OPERATORS.contains(&op)
+            {
+                self.pos += 1;
+                let value = self.parse_assignment()?;
+                return Ok(Expr::Assign(lvalue, op, Box::new(value)));
+            }
+            self.pos = start;
+        }
+        self.parse_conditional()
+    }
+
+    /// Parses a name and an optional `[index]` subscript.
+    fn parse_lvalue(&mut self) -> Result<Lvalue> {
+        let Some(Token::Name(name)) = self.tokens.get(self.pos).cloned() else {
+            let token = self
+                .tokens
+                .get(self.pos)
+                .map(token_text)
+                .unwrap_or_default();
+            anyhow::bail!(
+                "syntax error: operand expected (error token is \"{}\")",
+                token
+            )
+        };
+        self.pos += 1;
+        if self.peek_operator() != Some("[") {
+            return Ok(Lvalue {
+                name,
+                subscript: None,
+            });
+        }
+        self.pos += 1;
+        let start = self.pos;
+        let index = self.parse_comma()?;
+        let key = self.tokens[start..self.pos]
+            .iter()
+            .map(token_text)
+            .collect();
+        self.expect("]")?;
+        Ok(Lvalue {
+            name,
+            subscript: Some((Box::new(index), key)),
+        })
+    }
+
+    fn parse_conditional(&mut self) -> Result<Expr> {
+        let condition = self.parse_binary(0)?;
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let then = self.parse_comma()?;
+        self.expect(":")?;
+        let otherwise = self.parse_conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr> {
+        if level == PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+        let mut lhs = self.parse_binary(level + 1)?;
+        while let Some(op) = self.peek_operator()
+            && PRECEDENCE[level].contains(&op)
+        {
+            self.pos += 1;
+            // `**` is right associative.
+            let rhs = if op == "**" {
+                self.parse_binary(level)?
+            } else {
+                self.parse_binary(level + 1)?
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        match self.peek_operator() {
+            Some(op @ ("-" | "+" | "!" | "~")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                let lvalue = self.parse_lvalue()?;
+                Ok(Expr::Increment {
+                    lvalue,
+                    delta: if op == "++" { 1 } else { -1 },
+                    prefix: true,
+                })
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr> {
+        if let Some(Token::Name(_)) = self.tokens.get(self.pos) {
+            let lvalue = self.parse_lvalue()?;
+            return match self.peek_operator() {
+                Some(op @ ("++" | "--")) => {
+                    self.pos += 1;
+                    Ok(Expr::Increment {
+                        lvalue,
+                        delta: if op == "++" { 1 } else { -1 },
+                        prefix: false,
+                    })
+                }
+                _ => Ok(Expr::Variable(lvalue)),
+            };
+        }
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Operator("(")) => {
+                let expr = self.parse_comma()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(token) => anyhow::bail!(
+                "syntax error: operand expected (error token is \"{}\")",
+                token_text(&token)
+            ),
+            None => anyhow::bail!("syntax error: operand expected"),
+        }
+    }
+}
+
+/// The array key of an element: its text for associative arrays, or else its index.
+fn element_key(lvalue: &Lvalue, variables: &mut Variables, depth: usize) -> Result<Option<String>> {
+    anyhow::ensure!(
+        is_name(&lvalue.name),
+        "{}: invalid variable name",
+        lvalue.name
+    );
+    let Some((index, key)) = &lvalue.subscript else {
+        return Ok(None);
+    };
+    if variables.is_associative(&lvalue.name) {
+        return Ok(Some(key.clone()));
+    }
+    Ok(Some(eval(index, variables, depth)?.to_string()))
+}
+
+fn variable_value(
+    lvalue: &Lvalue,
+    key: Option<&str>,
+    variables: &mut Variables,
+    depth: usize,
+) -> Result<i64> {
+    let value = match key {
+        Some(key) => variables.element(&lvalue.name, key)?,
+        None => variables.parameter(&lvalue.name),
+    };
+    match value {
+        Some(value) if !value.trim().is_empty() => evaluate_nested(&value, variables, depth + 1),
+        None if variables.is_nounset() => Err(Unbound(lvalue.name.clone()).into()),
+        _ => Ok(0),
+    }
+}
+
+fn assign(lvalue: &Lvalue, key: Option<&str>, value: i64, variables: &mut Variables) -> Result<()> {
+    match key {
+        Some(key) => variables.set_element(&lvalue.name, key, value.to_string()),
+        None => variables.insert(lvalue.name.clone(), value.to_string()),
+    }
+}
+
+fn eval(expr: &Expr, variables: &mut Variables, depth: usize) -> Result<i64> {
+    let value = match expr {
+        Expr::Number(n) => *n,
+        Expr::Variable(lvalue) => {
+            let key = element_key(lvalue, variables, depth)?;
+            variable_value(lvalue, key.as_deref(), variables, depth)?
+        }
+        Expr::Unary(op, operand) => {
+            let value = eval(operand, variables, depth)?;
+            match *op {
+                "-" => value.wrapping_neg(),
+                "!" => (value == 0) as i64,
+                "~" => !value,
+                _ => value,
+            }
+        }
+        Expr::Increment {
+            lvalue,
+            delta,
+            prefix,
+        } => {
+            let key = element_key(lvalue, variables, depth)?;
+            let old = variable_value(lvalue, key.as_deref(), variables, depth)?;
+            let new = old.wrapping_add(*delta);
+            assign(lvalue, key.as_deref(), new, variables)?;
+            if *prefix { new } else { old }
+        }
+        Expr::Binary("&&", lhs, rhs) => {
+            (eval(lhs, variables, depth)? != 0 && eval(rhs, variables, depth)? != 0) as i64
+        }
+        Expr::Binary("||", lhs, rhs) => {
+            (eval(lhs, variables, depth)? != 0 || eval(rhs, variables, depth)? != 0) as i64
+        }
+        Expr::Binary(op, lhs, rhs) => {
+            let lhs = eval(lhs, variables, depth)?;
+            let rhs = eval(rhs, variables, depth)?;
+            binary(op, lhs, rhs)?
+        }
+        Expr::Conditional(condition, then, otherwise) => {
+            if eval(condition, variables, depth)? != 0 {
+                eval(then, variables, depth)?
+            } else {
+                eval(otherwise, variables, depth)?
+            }
+        }
+        Expr::Assign(lvalue, op, value) => {
+            let key = element_key(lvalue, variables, depth)?;
+            let rhs = eval(value, variables, depth)?;
+            let value = match op.strip_suffix('=').filter(|op| !op.is_empty()) {
+                Some(op) => binary(
+                    op,
+                    variable_value(lvalue, key.as_deref(), variables, depth)?,
+                    rhs,
+                )?,
+                None => rhs,
+            };
+            assign(lvalue, key.as_deref(), value, variables)?;
+            value
+        }
+        Expr::Comma(first, second) => {
+            eval(first, variables, depth)?;
+            eval(second, variables, depth)?
+        }
+    };
+    Ok(value)
+}
+
+fn binary(op: &str, lhs: i64, rhs: i64) -> Result<i64> {
+    let value = match op {
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" => {
+            anyhow::ensure!(rhs != 0, "division by 0");
+            if op == "/" {
+                lhs.wrapping_div(rhs)
+            } else {
+                lhs.wrapping_rem(rhs)
+            }
+        }
+        "**" => {
+            anyhow::ensure!(rhs >= 0, "exponent less than 0");
+            lhs.wrapping_pow(rhs.min(u32::MAX as i64) as u32)
+        }
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "&" => lhs & rhs,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        _ => anyhow::bail!("{}: unknown operator", op),
+    };
+    Ok(value)
+}
@@ -0,0 +1,341 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    rc::Rc,
+};
+
+use crate::lexer::Word;
+
+/// Commands separated by `;`, `&` or newlines.
+#[derive(Debug, Clone, Default)]
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    pub is_background_job: bool,
+}
+
+/// Pipelines joined by `&&` and `||`.
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirect>),
+    FunctionDefinition { name: String, body: Rc<Command> },
+}
+
+/// Defined functions by name.
+pub type Functions = BTreeMap<String, Rc<Command>>;
+
+#[derive(Debug, Clone)]
+pub struct SimpleCommand {
+    pub words: Vec<Word>, // leading `NAME=value` words are assignments
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone)]
+pub enum CompoundCommand {
+    Group(List),    // `{ list; }`
+    Subshell(List), // `( list )`, run in a forked child
+    If {
+        branches: Vec<(List, List)>, // condition and body of `if` and every `elif`
+        else_body: Option<List>,
+    },
+    While {
+        condition: List,
+        body: List,
+        until: bool,
+    },
+    For {
+        variable: String,
+        words: Option<Vec<Word>>, // `None` iterates over the positional parameters
+        body: List,
+    },
+    ArithmeticFor {
+        init: String,
+        condition: String,
+        step: String,
+        body: List,
+    },
+    Case {
+        word: Word,
+        items: Vec<CaseItem>,
+    },
+    Arithmetic(String),
+    Conditional(Conditional), // `[[ expression ]]`
+}
+
+/// An expression of a `[[ ]]` command.
+#[derive(Debug, Clone)]
+pub enum Conditional {
+    Word(Word), // true when not empty
+    Unary(String, Word),
+    Binary(Word, String, Word),
+    Not(Box<Conditional>),
+    And(Box<Conditional>, Box<Conditional>),
+    Or(Box<Conditional>, Box<Conditional>),
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+    pub terminator: CaseTerminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseTerminator {
+    Break,       // `;;`
+    FallThrough, // `;&` runs the next body without testing its patterns
+    Continue,    // `;;&` goes on testing the following patterns
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub fd: i32,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectKind {
+    Input,     // `<`
+    Output,    // `>`
+    Append,    // `>>`
+    Clobber,   // `>|`
+    Duplicate, // `>&` and `<&`
+    OutputAll, // `&>`
+    AppendAll, // `&>>`
+}
+
+// The `Display` impls write commands back on a single line, as shown by `jobs`.
+
+impl Display for List {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", item.and_or)?;
+            if item.is_background_job {
+                write!(f, " &")?;
+            } else if i + 1 < self.items.len() {
+                write!(f, ";")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for AndOr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            let op = match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            };
+            write!(f, " {op} {pipeline}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Pipeline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        for (i, command) in self.commands.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{command}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Simple(simple) => write!(f, "{simple}"),
+            Command::Compound(compound, redirects) => {
+                write!(f, "{compound}")?;
+                redirects.iter().try_for_each(|r| write!(f, " {r}"))
+            }
+            Command::FunctionDefinition { name, body } => write!(f, "{name} () {body}"),
+        }
+    }
+}
+
+impl Display for SimpleCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let words = self.words.iter().map(Word::to_string);
+        let redirects = self.redirects.iter().map(Redirect::to_string);
+        let parts: Vec<String> = words.chain(redirects).collect();
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Writes a list as the body of a compound command, terminated by `;`.
+fn write_body(f: &mut Formatter<'_>, list: &List) -> fmt::Result {
+    match list.items.last() {
+        Some(item) if item.is_background_job => write!(f, " {list}"),
+        Some(_) => write!(f, " {list};"),
+        None => Ok(()),
+    }
+}
+
+impl Display for CompoundCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CompoundCommand::Group(list) => {
+                write!(f, "{{")?;
+                write_body(f, list)?;
+                write!(f, " }}")
+            }
+            CompoundCommand::Subshell(list) => write!(f, "( {list} )"),
+            CompoundCommand::If {
+                branches,
+                else_body,
+            } => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    write!(f, "{}", if i == 0 { "if" } else { " elif" })?;
+                    write_body(f, condition)?;
+                    write!(f, " then")?;
+                    write_body(f, body)?;
+                }
+                if let Some(else_body) = else_body {
+                    write!(f, " else")?;
+                    write_body(f, else_body)?;
+                }
+                write!(f, " fi")
+            }
+            CompoundCommand::While {
+                condition,
+                body,
+                until,
+            } => {
+                write!(f, "{}", if *until { "until" } else { "while" })?;
+                write_body(f, condition)?;
+                write!(f, " do")?;
+                write_body(f, body)?;
+                write!(f, " done")
+            }
+            CompoundCommand::For {
+                variable,
+                words,
+                body,
+            } => {
+                write!(f, "for {variable}")?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    words.iter().try_for_each(|word| write!(f, " {word}"))?;
+                }
+                write!(f, "; do")?;
+                write_body(f, body)?;
+                write!(f, " done")
+            }
+            CompoundCommand::ArithmeticFor {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                write!(f, "for (({init}; {condition}; {step})); do")?;
+                write_body(f, body)?;
+                write!(f, " done")
+            }
+            CompoundCommand::Case { word, items } => {
+                write!(f, "case {word} in")?;
+                for item in items {
+                    let patterns: Vec<String> = item.patterns.iter().map(Word::to_string).collect();
+                    write!(f, " {})", patterns.join(" | "))?;
+                    if !item.body.items.is_empty() {
+                        write!(f, " {}", item.body)?;
+                    }
+                    let terminator = match item.terminator {
+                        CaseTerminator::Break => ";;",
+                        CaseTerminator::FallThrough => ";&",
+                        CaseTerminator::Continue => ";;&",
+                    };
+                    write!(f, " {terminator}")?;
+                }
+                write!(f, " esac")
+            }
+            CompoundCommand::Arithmetic(expression) => write!(f, "(({expression}))"),
+            CompoundCommand::Conditional(expression) => write!(f, "[[ {expression} ]]"),
+        }
+    }
+}
+
+impl Display for Conditional {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Conditional::Word(word) => write!(f, "{word}"),
+            Conditional::Unary(op, word) => write!(f, "{op} {word}"),
+            Conditional::Binary(lhs, op, rhs) => write!(f, "{lhs} {op} {rhs}"),
+            Conditional::Not(expression) => write!(f, "! {expression}"),
+            Conditional::And(lhs, rhs) => write!(f, "{lhs} && {rhs}"),
+            Conditional::Or(lhs, rhs) => write!(f, "( {lhs} || {rhs} )"),
+        }
+    }
+}
+
+impl Display for Redirect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (default_fd, op) = match self.kind {
+            RedirectKind::Input => (0, "<"),
+            RedirectKind::Output => (1, ">"),
+            RedirectKind::Append => (1, ">>"),
+            RedirectKind::Clobber => (1, ">|"),
+            RedirectKind::Duplicate if self.fd == 0 => (0, "<&"),
+            RedirectKind::Duplicate => (1, ">&"),
+            RedirectKind::OutputAll => (1, "&>"),
+            RedirectKind::AppendAll => (1, "&>>"),
+        };
+        if self.fd != default_fd {
+            write!(f, "{}", self.fd)?;
+        }
+        write!(f, "{op}{}", self.target)
+    }
+}
+
+/// Writes a function definition the way `declare -f` shows it, one command per line.
+pub fn function_definition(name: &str, body: &Command) -> String {
+    let body = match body {
+        Command::Compound(CompoundCommand::Group(list), redirects) => {
+            let mut lines = vec![String::from("{ ")];
+            for item in &list.items {
+                let background = if item.is_background_job { " &" } else { "" };
+                lines.push(format!("    {}{}", item.and_or, background));
+            }
+            let redirects: String = redirects.iter().map(|r| format!(" {r}")).collect();
+            lines.push(format!("}}{redirects}"));
+            lines.join("\n")
+        }
+        body => body.to_string(),
+    };
+    format!("{name} () \n{body}")
+}
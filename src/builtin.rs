@@ -1,19 +1,38 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     env::{current_dir, home_dir, set_current_dir},
     fmt::Display,
-    path::Path,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
     str::FromStr,
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
 
 use crate::{
-    command::find_excutable,
+    ast::{Functions, function_definition},
+    command::{edit_distance, find_excutable, io_error_message},
+    condition,
+    expansion::expand_subscript,
     job::{Job, JobStatus},
+    lexer::is_name,
+    options::Options,
+    parser::Aliases,
+    printf,
     readline::history::History,
+    signal::{self, Trap, Traps},
+    variable::{Attributes, Value, Variable, Variables},
 };
 
+/// Names of the builtins, for completion and suggestions.
+pub const NAMES: [&str; 31] = [
+    "cd", "exit", "echo", "history", "pwd", "type", "jobs", "complete", "declare", "shift",
+    "shopt", "break", "continue", "true", "false", ":", "local", "return", "test", "[", "alias",
+    "unalias", "read", "printf", "unset", "readonly", "set", "trap", "pushd", "popd", "dirs",
+];
+
 #[derive(Debug, PartialEq)]
 pub enum Builtin {
     Cd,
@@ -25,6 +44,28 @@ pub enum Builtin {
     Jobs,
     Complete,
     Declare,
+    Shift,
+    Shopt,
+    Break,
+    Continue,
+    True,
+    False,
+    Colon,
+    Local,
+    Return,
+    Test,
+    Bracket,
+    Alias,
+    Unalias,
+    Read,
+    Printf,
+    Unset,
+    Readonly,
+    Set,
+    Trap,
+    Pushd,
+    Popd,
+    Dirs,
 }
 
 impl Display for Builtin {
@@ -39,6 +80,28 @@ impl Display for Builtin {
             Self::Jobs => write!(f, "jobs"),
             Self::Complete => write!(f, "complete"),
             Self::Declare => write!(f, "declare"),
+            Self::Shift => write!(f, "shift"),
+            Self::Shopt => write!(f, "shopt"),
+            Self::Break => write!(f, "break"),
+            Self::Continue => write!(f, "continue"),
+            Self::True => write!(f, "true"),
+            Self::False => write!(f, "false"),
+            Self::Colon => write!(f, ":"),
+            Self::Local => write!(f, "local"),
+            Self::Return => write!(f, "return"),
+            Self::Test => write!(f, "test"),
+            Self::Bracket => write!(f, "["),
+            Self::Alias => write!(f, "alias"),
+            Self::Unalias => write!(f, "unalias"),
+            Self::Read => write!(f, "read"),
+            Self::Printf => write!(f, "printf"),
+            Self::Unset => write!(f, "unset"),
+            Self::Readonly => write!(f, "readonly"),
+            Self::Set => write!(f, "set"),
+            Self::Trap => write!(f, "trap"),
+            Self::Pushd => write!(f, "pushd"),
+            Self::Popd => write!(f, "popd"),
+            Self::Dirs => write!(f, "dirs"),
         }
     }
 }
@@ -56,35 +119,318 @@ impl FromStr for Builtin {
             "jobs" => Ok(Self::Jobs),
             "complete" => Ok(Self::Complete),
             "declare" => Ok(Self::Declare),
+            "shift" => Ok(Self::Shift),
+            "shopt" => Ok(Self::Shopt),
+            "break" => Ok(Self::Break),
+            "continue" => Ok(Self::Continue),
+            "true" => Ok(Self::True),
+            "false" => Ok(Self::False),
+            ":" => Ok(Self::Colon),
+            "local" => Ok(Self::Local),
+            "return" => Ok(Self::Return),
+            "test" => Ok(Self::Test),
+            "[" => Ok(Self::Bracket),
+            "alias" => Ok(Self::Alias),
+            "unalias" => Ok(Self::Unalias),
+            "read" => Ok(Self::Read),
+            "printf" => Ok(Self::Printf),
+            "unset" => Ok(Self::Unset),
+            "readonly" => Ok(Self::Readonly),
+            "set" => Ok(Self::Set),
+            "trap" => Ok(Self::Trap),
+            "pushd" => Ok(Self::Pushd),
+            "popd" => Ok(Self::Popd),
+            "dirs" => Ok(Self::Dirs),
             _ => anyhow::bail!("Not a builtin command"),
         }
     }
 }
 
-pub fn cd(args: Vec<String>) -> Result<String> {
-    let mut home = String::new();
-    if args.is_empty() || args[0].as_bytes().first() == Some(&b'~') {
-        if let Some(h) = home_dir() {
-            home = h.display().to_string();
-        } else {
-            anyhow::bail!("Impossible to get home dir")
+/// Changes the working directory, to `$HOME` without an operand or to `$OLDPWD` with `-`,
+/// searching `CDPATH` for relative names. `..` goes back up the path in `$PWD` with `-L`, the
+/// default, or up the directory that symlinks lead to with `-P`. Updates `PWD` and `OLDPWD`.
+/// With `cdspell`, an interactive shell corrects small typos in a directory that is not found.
+pub fn cd(args: Vec<String>, variables: &mut Variables, options: &Options) -> Result<String> {
+    let mut physical = false;
+    let mut args = args.into_iter().peekable();
+    while let Some(flags) = args.next_if(|arg| arg.len() > 1 && arg.starts_with('-')) {
+        if flags == "--" {
+            break;
+        }
+        for flag in flags.chars().skip(1) {
+            match flag {
+                'L' => physical = false,
+                'P' => physical = true,
+                _ => anyhow::bail!("cd: -{}: invalid option\ncd: usage: cd [-L|-P] [dir]", flag),
+            }
+        }
+    }
+    let args: Vec<String> = args.collect();
+    let (dir, print) = match args.as_slice() {
+        [] => match variables
+            .parameter("HOME")
+            .or_else(|| home_dir().map(|home| home.display().to_string()))
+        {
+            Some(home) => (home, false),
+            None => anyhow::bail!("cd: HOME not set"),
+        },
+        [dir] if dir == "-" => match variables.parameter("OLDPWD") {
+            Some(oldpwd) => (oldpwd, true),
+            None => anyhow::bail!("cd: OLDPWD not set"),
+        },
+        [dir] => (dir.clone(), false),
+        _ => anyhow::bail!("cd: too many arguments"),
+    };
+    let searched = match change_directory(&Builtin::Cd, &dir, physical, variables) {
+        Ok(searched) => searched,
+        Err(e) => {
+            let cdspell = options.shopt("cdspell") && variables.is_interactive();
+            let Some(corrected) = cdspell.then(|| spelling_correction(&dir)).flatten() else {
+                return Err(e);
+            };
+            change_directory(&Builtin::Cd, &corrected, physical, variables)?;
+            return Ok(corrected);
+        }
+    };
+    // Like bash, say where `-` or a `CDPATH` entry led.
+    match print || searched {
+        true => Ok(variables.parameter("PWD").unwrap_or_default()),
+        false => Ok(String::new()),
+    }
+}
+
+/// Changes to `dir`, searching `CDPATH` for relative names, and updates `PWD` and `OLDPWD`.
+/// Returns whether a `CDPATH` entry led there.
+fn change_directory(
+    builtin: &Builtin,
+    dir: &str,
+    physical: bool,
+    variables: &mut Variables,
+) -> Result<bool> {
+    let mut target = dir.to_string();
+    let mut searched = false;
+    let relative = !dir.starts_with('/') && !matches!(dir.split('/').next(), Some("." | ".."));
+    if relative && let Some(cdpath) = variables.parameter("CDPATH") {
+        for entry in cdpath.split(':') {
+            let candidate = match entry {
+                "" => dir.to_string(),
+                entry => format!("{}/{}", entry.trim_end_matches('/'), dir),
+            };
+            if Path::new(&candidate).is_dir() {
+                searched = !entry.is_empty();
+                target = candidate;
+                break;
+            }
+        }
+    }
+    let old = variables.working_directory();
+    let logical = match (&old, target.starts_with('/')) {
+        (_, true) => Some(logical_path("/", &target)),
+        (Some(old), false) => Some(logical_path(old, &target)),
+        (None, false) => None,
+    };
+    // A logical path that does not lead anywhere falls back to following the symlinks.
+    let pwd = match logical {
+        Some(logical) if !physical && set_current_dir(&logical).is_ok() => logical,
+        _ => {
+            if let Err(e) = set_current_dir(&target) {
+                anyhow::bail!("{}: {}: {}", builtin, dir, io_error_message(&e));
+            }
+            current_dir()?.display().to_string()
         }
+    };
+    if let Some(old) = old {
+        variables.insert(String::from("OLDPWD"), old)?;
+    }
+    variables.insert(String::from("PWD"), pwd)?;
+    Ok(searched)
+}
+
+/// Saves the working directory on the directory stack and changes to `dir`. `+N` or `-N`
+/// rotates the stack to bring that entry to the top, and no operand swaps the top two.
+pub fn pushd(args: Vec<String>, variables: &mut Variables) -> Result<String> {
+    let mut stack = variables.directory_stack();
+    match args.as_slice() {
+        [] if stack.len() < 2 => anyhow::bail!("pushd: no other directory"),
+        [] => stack.swap(0, 1),
+        [arg] => match stack_index(&Builtin::Pushd, arg, stack.len())? {
+            Some(n) => stack.rotate_left(n),
+            None => {
+                change_directory(&Builtin::Pushd, arg, false, variables)?;
+                variables.directories.insert(0, stack.remove(0));
+                return dirs(Vec::new(), variables);
+            }
+        },
+        _ => anyhow::bail!("pushd: too many arguments"),
     }
-    let path_string = if args.is_empty() {
-        home
-    } else if args[0].as_bytes().first() == Some(&b'~') {
-        format!("{}{}", home, &args[0][1..].to_string())
+    change_directory(&Builtin::Pushd, &stack[0], false, variables)?;
+    variables.directories = stack.split_off(1);
+    dirs(Vec::new(), variables)
+}
+
+/// Removes the top of the directory stack and changes to the new top, or removes the entry
+/// given as `+N` or `-N`.
+pub fn popd(args: Vec<String>, variables: &mut Variables) -> Result<String> {
+    let stack = variables.directory_stack();
+    anyhow::ensure!(stack.len() > 1, "popd: directory stack empty");
+    let n = match args.as_slice() {
+        [] => 0,
+        [arg] => stack_index(&Builtin::Popd, arg, stack.len())?
+            .ok_or_else(|| anyhow::anyhow!("popd: {}: invalid argument", arg))?,
+        _ => anyhow::bail!("popd: too many arguments"),
+    };
+    if n == 0 {
+        change_directory(&Builtin::Popd, &stack[1], false, variables)?;
+        variables.directories.remove(0);
     } else {
-        args[0].to_string()
+        variables.directories.remove(n - 1);
+    }
+    dirs(Vec::new(), variables)
+}
+
+/// Prints the directory stack, abbreviating the home directory as `~` unless `-l` is given,
+/// one entry per line with `-p`, numbered with `-v`, or only the `+N` or `-N` entry. `-c`
+/// clears it.
+pub fn dirs(args: Vec<String>, variables: &mut Variables) -> Result<String> {
+    let stack = variables.directory_stack();
+    let mut long = false;
+    let mut lines = false;
+    let mut numbered = false;
+    let mut index = None;
+    for arg in &args {
+        if let Some(n) = stack_index(&Builtin::Dirs, arg, stack.len())? {
+            index = Some(n);
+            continue;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            anyhow::bail!("dirs: {}: invalid argument", arg);
+        };
+        for flag in flags.chars() {
+            match flag {
+                'c' => {
+                    variables.directories.clear();
+                    return Ok(String::new());
+                }
+                'l' => long = true,
+                'p' => lines = true,
+                'v' => numbered = true,
+                _ => anyhow::bail!(
+                    "dirs: -{}: invalid option\ndirs: usage: dirs [-clpv] [+N] [-N]",
+                    flag
+                ),
+            }
+        }
+    }
+    let home = variables.parameter("HOME").filter(|home| !home.is_empty());
+    let shown = |dir: &str| match home.as_deref().and_then(|home| dir.strip_prefix(home)) {
+        Some(rest) if !long && (rest.is_empty() || rest.starts_with('/')) => format!("~{}", rest),
+        _ => dir.to_string(),
+    };
+    if let Some(n) = index {
+        return Ok(shown(&stack[n]));
+    }
+    let entries: Vec<String> = stack
+        .iter()
+        .enumerate()
+        .map(|(i, dir)| match numbered {
+            true => format!("{:2}  {}", i, shown(dir)),
+            false => shown(dir),
+        })
+        .collect();
+    Ok(entries.join(if lines || numbered { "\n" } else { " " }))
+}
+
+/// Index into a directory stack of `len` entries for `+N`, counting from the top, or `-N`,
+/// from the bottom, or `None` if `arg` is not of that form.
+fn stack_index(builtin: &Builtin, arg: &str, len: usize) -> Result<Option<usize>> {
+    let Some(digits) = arg.strip_prefix(['+', '-']) else {
+        return Ok(None);
     };
-    match set_current_dir(Path::new(&path_string)) {
-        Ok(_) => Ok(String::new()),
-        Err(_) => anyhow::bail!("cd: {}: No such file or directory", path_string),
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(None);
+    }
+    match digits.parse::<usize>() {
+        Ok(n) if n < len && arg.starts_with('+') => Ok(Some(n)),
+        Ok(n) if n < len => Ok(Some(len - 1 - n)),
+        _ if len < 2 => anyhow::bail!("{}: directory stack empty", builtin),
+        _ => anyhow::bail!("{}: {}: directory stack index out of range", builtin, arg),
+    }
+}
+
+/// `dir` with each component that does not exist replaced by a directory entry one typo away:
+/// a missing, extra, wrong or swapped character.
+fn spelling_correction(dir: &str) -> Option<String> {
+    let mut corrected = PathBuf::from(if dir.starts_with('/') { "/" } else { "" });
+    for component in dir.split('/').filter(|component| !component.is_empty()) {
+        if corrected.join(component).exists() {
+            corrected.push(component);
+            continue;
+        }
+        let parent = match corrected.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => corrected.as_path(),
+        };
+        let name = fs::read_dir(parent)
+            .ok()?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| edit_distance(component, name) == 1)
+            .min()?;
+        corrected.push(name);
     }
+    Some(corrected.display().to_string())
 }
 
-pub fn echo(args: Vec<String>) -> Result<String> {
-    Ok(args.join(" "))
+/// Joins `path` to the directory `base`, removing `.` and going back a component for `..`
+/// without following symlinks.
+fn logical_path(base: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in base.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    format!("/{}", components.join("/"))
+}
+
+/// Joins the arguments with a trailing newline. Leading `-n`, `-e` and `-E` arguments drop the
+/// newline and turn escape sequences on or off; `xpg_echo` turns them on by default.
+pub fn echo(args: Vec<String>, options: &Options) -> Result<String> {
+    let mut newline = true;
+    let mut escapes = options.shopt("xpg_echo");
+    let mut args = args.as_slice();
+    while let Some((arg, rest)) = args.split_first()
+        && let Some(flags) = arg.strip_prefix('-')
+        && !flags.is_empty()
+        && flags.chars().all(|c| matches!(c, 'n' | 'e' | 'E'))
+    {
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        args = rest;
+    }
+    let mut output = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            output.push(' ');
+        }
+        if !escapes {
+            output.push_str(arg);
+        } else if !printf::expand_escapes(arg, &mut output, false) {
+            return Ok(output);
+        }
+    }
+    if newline {
+        output.push('\n');
+    }
+    Ok(output)
 }
 
 pub fn complete(mut args: Vec<String>, completers: &mut HashMap<String, String>) -> Result<String> {
@@ -174,13 +520,44 @@ pub fn jobs(jobs: &[Job]) -> Result<String> {
     Ok(output.join("\n"))
 }
 
-pub fn pwd() -> Result<String> {
-    let dir = current_dir()?;
-    Ok(dir.display().to_string())
+/// Prints the working directory: `$PWD`, which keeps symlinks, with `-L`, the default, or the
+/// directory with symlinks resolved with `-P`.
+pub fn pwd(args: Vec<String>, variables: &Variables) -> Result<String> {
+    let mut physical = false;
+    for arg in &args {
+        if arg == "--" {
+            break;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            break;
+        };
+        for flag in flags.chars() {
+            match flag {
+                'L' => physical = false,
+                'P' => physical = true,
+                _ => anyhow::bail!("pwd: -{}: invalid option\npwd: usage: pwd [-LP]", flag),
+            }
+        }
+    }
+    let dir = match physical {
+        true => current_dir().map(|dir| dir.display().to_string()).ok(),
+        false => variables.working_directory(),
+    };
+    dir.ok_or_else(|| {
+        anyhow::anyhow!("pwd: error retrieving current directory: No such file or directory")
+    })
 }
 
-pub fn r#type(args: Vec<String>) -> Result<String> {
-    let ouput = if Builtin::from_str(&args[0]).is_ok() {
+pub fn r#type(args: Vec<String>, aliases: &Aliases, functions: &Functions) -> Result<String> {
+    let ouput = if let Some(value) = aliases.get(&args[0]) {
+        format!("{} is aliased to `{}'", args[0], value)
+    } else if let Some(body) = functions.get(&args[0]) {
+        format!(
+            "{} is a function\n{}",
+            args[0],
+            function_definition(&args[0], body)
+        )
+    } else if Builtin::from_str(&args[0]).is_ok() {
         format!("{} is a shell builtin", args[0])
     } else if let Some(ex_path) = find_excutable(&args[0]) {
         format!("{} is {}", args[0], ex_path)
@@ -190,25 +567,988 @@ pub fn r#type(args: Vec<String>) -> Result<String> {
     Ok(ouput)
 }
 
-pub fn declare(args: Vec<String>, variables: &mut HashMap<String, String>) -> Result<String> {
-    if args[0] == "-p" {
-        anyhow::ensure!(args.len() == 2);
-        match variables.get(&args[1]) {
-            Some(val) => return Ok(format!("declare -- {}=\"{}\"", args[1], val)),
-            None => anyhow::bail!("declare: {}: not found", args[1]),
+/// Declares variables and sets their attributes, for `declare`, `local` and `readonly`. Without
+/// names, lists the variables that have the given attributes.
+pub fn declare(
+    builtin: &Builtin,
+    args: Vec<String>,
+    variables: &mut Variables,
+    functions: &Functions,
+) -> Result<String> {
+    if *builtin == Builtin::Local && !variables.in_function() {
+        anyhow::bail!("local: can only be used in a function")
+    }
+    if let Some(flag @ ("-f" | "-F")) = args.first().map(String::as_str) {
+        let names: Vec<&String> = match args.len() {
+            1 => functions.keys().collect(),
+            _ => args[1..].iter().collect(),
+        };
+        let mut output = Vec::new();
+        for name in names {
+            let Some(body) = functions.get(name) else {
+                anyhow::bail!("{}: {}: not found", builtin, name)
+            };
+            output.push(match (flag, args.len()) {
+                ("-F", 1) => format!("declare -f {name}"),
+                ("-F", _) => name.to_string(),
+                _ => function_definition(name, body),
+            });
         }
+        return Ok(output.join("\n"));
     }
-    let rgx = regex::Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$")?;
-    for pair in args {
-        if let Some((key, val)) = pair.split_once('=')
-            && !key.is_empty()
-            && !val.is_empty()
-            && rgx.is_match(key)
-        {
-            variables.insert(key.to_owned(), val.to_owned());
+    let mut set = Attributes {
+        readonly: *builtin == Builtin::Readonly,
+        ..Attributes::default()
+    };
+    let mut unset = Attributes::default();
+    let mut kind = None; // `a` for indexed or `A` for associative arrays
+    let mut print = false;
+    let mut global = *builtin == Builtin::Readonly;
+    let mut names = Vec::new();
+    let mut args = args.into_iter();
+    for arg in args.by_ref() {
+        let (on, flags) = match arg.split_at_checked(1) {
+            _ if arg == "--" => break,
+            Some(("-", flags)) if !flags.is_empty() => (true, flags),
+            Some(("+", flags)) if !flags.is_empty() => (false, flags),
+            _ => {
+                names.push(arg);
+                break;
+            }
+        };
+        for flag in flags.chars() {
+            let attribute = match on {
+                true => &mut set,
+                false => &mut unset,
+            };
+            match flag {
+                'a' | 'A' if on => kind = Some(flag),
+                'a' | 'A' => anyhow::bail!(
+                    "{}: +{}: cannot destroy array variables in this way",
+                    builtin,
+                    flag
+                ),
+                'g' => global = true,
+                'p' => print = true,
+                'i' => attribute.integer = true,
+                'l' => attribute.lowercase = true,
+                'n' => attribute.nameref = true,
+                'r' => attribute.readonly = true,
+                'u' => attribute.uppercase = true,
+                'x' => attribute.exported = true,
+                _ => anyhow::bail!("{}: {}{}: invalid option", builtin, &arg[..1], flag),
+            }
+        }
+    }
+    names.extend(args);
+    if unset.readonly {
+        let name = names
+            .first()
+            .map(|arg| arg.split('=').next().unwrap_or(arg));
+        anyhow::bail!("{}: {}: readonly variable", builtin, name.unwrap_or("-r"))
+    }
+    if names.is_empty() {
+        let attributes = set != Attributes::default() || kind.is_some();
+        return Ok(match (builtin, print || attributes) {
+            (Builtin::Local, _) => String::new(),
+            (_, false) => listing(variables, functions),
+            _ => variables
+                .variables()
+                .into_iter()
+                .filter(|(_, variable)| has_attributes(variable, set, kind))
+                .map(|(name, variable)| declaration(name, variable))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        });
+    }
+    if print {
+        let mut output = Vec::new();
+        for name in names {
+            match variables.variable(&name) {
+                Some(variable) => output.push(declaration(&name, variable)),
+                None => anyhow::bail!("{}: {}: not found", builtin, name),
+            }
+        }
+        return Ok(output.join("\n"));
+    }
+    let local = !global && variables.in_function();
+    for arg in names {
+        declare_name(&arg, set, unset, kind, local, variables)
+            .map_err(|e| anyhow::anyhow!("{}: {}", builtin, e))?;
+    }
+    Ok(String::new())
+}
+
+/// Declares one `NAME`, `NAME=value` or `NAME+=value` argument of `declare`.
+fn declare_name(
+    arg: &str,
+    set: Attributes,
+    unset: Attributes,
+    kind: Option<char>,
+    local: bool,
+    variables: &mut Variables,
+) -> Result<()> {
+    let (name, value) = match arg.split_once('=') {
+        Some((name, value)) => (name, Some(value.to_string())),
+        None => (arg, None),
+    };
+    let (name, append) = match name.strip_suffix('+') {
+        Some(name) if value.is_some() => (name.to_string(), true),
+        _ => (name.to_string(), false),
+    };
+    anyhow::ensure!(is_name(&name), "`{}': not a valid identifier", arg);
+    if local {
+        variables.local(name.clone(), None)?;
+    }
+    let old = variables.attributes(&name);
+    let mut attributes = Attributes {
+        integer: (old.integer || set.integer) && !unset.integer,
+        lowercase: (old.lowercase || set.lowercase) && !unset.lowercase,
+        nameref: (old.nameref || set.nameref) && !unset.nameref,
+        readonly: old.readonly,
+        uppercase: (old.uppercase || set.uppercase) && !unset.uppercase,
+        exported: (old.exported || set.exported) && !unset.exported,
+    };
+    // `-l` replaces `-u` and the other way around, and both together cancel out.
+    if set.lowercase {
+        attributes.uppercase = false;
+    }
+    if set.uppercase {
+        attributes.lowercase = false;
+    }
+    if old.readonly && (value.is_some() || attributes != old) {
+        anyhow::bail!("{}: readonly variable", name)
+    }
+    match (kind, variables.get(&name).cloned()) {
+        (Some('a'), Some(Value::Associative(_))) => {
+            anyhow::bail!("{}: cannot convert associative to indexed array", name)
+        }
+        (Some('A'), Some(Value::Indexed(_))) => {
+            anyhow::bail!("{}: cannot convert indexed to associative array", name)
+        }
+        (Some('a'), Some(Value::Scalar(scalar))) => {
+            variables.set(name.clone(), Value::Indexed(BTreeMap::from([(0, scalar)])))?
+        }
+        (Some('A'), Some(Value::Scalar(scalar))) => variables.set(
+            name.clone(),
+            Value::Associative(BTreeMap::from([(String::from("0"), scalar)])),
+        )?,
+        (Some('a'), None) => variables.set(name.clone(), Value::Indexed(BTreeMap::new()))?,
+        (Some('A'), None) => variables.set(name.clone(), Value::Associative(BTreeMap::new()))?,
+        _ => {}
+    }
+    variables.set_attributes(&name, attributes);
+    if let Some(mut value) = value {
+        if attributes.nameref {
+            // The value of a nameref is the name it refers to, not assigned through it.
+            variables.variable_mut(&name).value = Some(Value::Scalar(value));
         } else {
-            anyhow::bail!("declare: `{}': not a valid identifier", pair)
+            if append && let Some(old) = variables.get(&name).and_then(Value::scalar) {
+                value = match attributes.integer {
+                    true => format!("{}+({})", old, value),
+                    false => old.clone() + &value,
+                };
+            }
+            variables.insert(name.clone(), value)?;
+        }
+    }
+    if set.readonly {
+        attributes.readonly = true;
+        variables.set_attributes(&name, attributes);
+    }
+    Ok(())
+}
+
+/// Whether a variable has all the attributes given to `declare`, and is of the given array kind.
+fn has_attributes(variable: &Variable, attributes: Attributes, kind: Option<char>) -> bool {
+    let has = variable.attributes;
+    let kind_matches = match kind {
+        Some('a') => matches!(variable.value, Some(Value::Indexed(_))),
+        Some(_) => matches!(variable.value, Some(Value::Associative(_))),
+        None => true,
+    };
+    kind_matches
+        && (!attributes.integer || has.integer)
+        && (!attributes.lowercase || has.lowercase)
+        && (!attributes.nameref || has.nameref)
+        && (!attributes.readonly || has.readonly)
+        && (!attributes.uppercase || has.uppercase)
+        && (!attributes.exported || has.exported)
+}
+
+/// All variables with a value as `NAME=value`, followed by all functions, as `declare` lists them.
+fn listing(variables: &Variables, functions: &Functions) -> String {
+    let mut output = Vec::new();
+    for (name, variable) in variables.variables() {
+        match &variable.value {
+            Some(Value::Scalar(value)) => output.push(format!("{}={}", name, quote_word(value))),
+            Some(value) => output.push(format!("{}={}", name, array_elements(value))),
+            None => {}
+        }
+    }
+    let mut names: Vec<_> = functions.keys().collect();
+    names.sort();
+    for name in names {
+        output.push(function_definition(name, &functions[name]));
+    }
+    output.join("\n")
+}
+
+/// A variable as `declare -p` prints it, in a form the shell can read back.
+fn declaration(name: &str, variable: &Variable) -> String {
+    let attributes = variable.attributes;
+    let mut flags: String = [
+        (matches!(variable.value, Some(Value::Indexed(_))), 'a'),
+        (matches!(variable.value, Some(Value::Associative(_))), 'A'),
+        (attributes.integer, 'i'),
+        (attributes.lowercase, 'l'),
+        (attributes.nameref, 'n'),
+        (attributes.readonly, 'r'),
+        (attributes.uppercase, 'u'),
+        (attributes.exported, 'x'),
+    ]
+    .into_iter()
+    .filter_map(|(on, flag)| on.then_some(flag))
+    .collect();
+    if flags.is_empty() {
+        flags.push('-');
+    }
+    match &variable.value {
+        None => format!("declare -{} {}", flags, name),
+        Some(Value::Scalar(value)) => {
+            format!("declare -{} {}={}", flags, name, quote_value(value))
+        }
+        Some(value) => format!("declare -{} {}={}", flags, name, array_elements(value)),
+    }
+}
+
+/// The elements of an array as `([key]="value" ...)`.
+fn array_elements(value: &Value) -> String {
+    let element = |key: String, value: &String| format!("[{}]={}", key, quote_value(value));
+    match value {
+        Value::Scalar(value) => quote_value(value),
+        Value::Indexed(elements) => {
+            let elements: Vec<String> = elements
+                .iter()
+                .map(|(index, value)| element(index.to_string(), value))
+                .collect();
+            format!("({})", elements.join(" "))
+        }
+        Value::Associative(elements) if elements.is_empty() => String::from("()"),
+        Value::Associative(elements) => {
+            let elements: String = elements
+                .iter()
+                .map(|(key, value)| {
+                    let key = match key
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || "_-.,:/@%+".contains(c))
+                    {
+                        true => key.clone(),
+                        false => quote_value(key),
+                    };
+                    element(key, value) + " "
+                })
+                .collect();
+            format!("({})", elements)
+        }
+    }
+}
+
+/// Single-quotes a value when it needs quoting, or uses `$'...'` for control characters.
+pub fn quote_word(value: &str) -> String {
+    if value.chars().any(|c| c.is_control()) {
+        return printf::quote(value);
+    }
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-./,:=+@%^".contains(c))
+    {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Double-quotes a value for `declare -p`, or uses `$'...'` for control characters.
+fn quote_value(value: &str) -> String {
+    if value.chars().any(|c| c.is_control()) {
+        return printf::quote(value);
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Unsets variables, array elements given as `name[subscript]`, or functions with `-f`. Without
+/// `-v` or `-f`, a name that is not a variable unsets the function of that name. Returns 1 when
+/// a name could not be unset.
+pub fn unset(
+    args: Vec<String>,
+    variables: &mut Variables,
+    functions: &mut Functions,
+) -> Result<i32> {
+    let mut function = false;
+    let mut variable = false;
+    let mut nameref = false;
+    let mut args = args.into_iter().peekable();
+    while let Some(flags) = args.next_if(|arg| arg.len() > 1 && arg.starts_with('-')) {
+        if flags == "--" {
+            break;
+        }
+        for flag in flags.chars().skip(1) {
+            match flag {
+                'f' => function = true,
+                'v' => variable = true,
+                'n' => nameref = true,
+                _ => anyhow::bail!(
+                    "unset: -{}: invalid option\nunset: usage: unset [-f] [-v] [-n] [name ...]",
+                    flag
+                ),
+            }
+        }
+    }
+    if function && variable {
+        eprintln!("unset: cannot simultaneously unset a function and a variable");
+        return Ok(1);
+    }
+    let mut status = 0;
+    for arg in args {
+        let unset = match (function, variable) {
+            (true, _) => {
+                functions.remove(&arg);
+                Ok(())
+            }
+            (false, false) if !arg.ends_with(']') && variables.variable(&arg).is_none() => {
+                functions.remove(&arg);
+                Ok(())
+            }
+            _ => unset_variable(&arg, nameref, variables),
+        };
+        if let Err(e) = unset {
+            eprintln!("unset: {e}");
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// Unsets a variable or an array element. With `nameref`, unsets a nameref itself rather than the
+/// variable it refers to.
+fn unset_variable(arg: &str, nameref: bool, variables: &mut Variables) -> Result<()> {
+    if let Some((name, subscript)) = arg.strip_suffix(']').and_then(|arg| arg.split_once('['))
+        && is_name(name)
+    {
+        let key = expand_subscript(name, subscript, variables)?;
+        return variables.remove_element(name, &key);
+    }
+    anyhow::ensure!(is_name(arg), "`{}': not a valid identifier", arg);
+    let Some(attributes) = variables.variable(arg).map(|variable| variable.attributes) else {
+        return Ok(());
+    };
+    if !(nameref && attributes.nameref) {
+        variables.remove(arg)?;
+        return Ok(());
+    }
+    anyhow::ensure!(
+        !attributes.readonly,
+        "{}: cannot unset: readonly variable",
+        arg
+    );
+    variables.replace(arg.to_string(), None);
+    Ok(())
+}
+
+pub fn shift(args: Vec<String>, variables: &mut Variables) -> Result<String> {
+    let n = match args.first() {
+        Some(n) => n
+            .parse::<usize>()
+            .map_err(|_| anyhow::anyhow!("shift: {}: numeric argument required", n))?,
+        None => 1,
+    };
+    variables.shift(n)?;
+    Ok(String::new())
+}
+
+/// Sets or unsets shell options, given as flags or with `-o NAME`, and sets the positional
+/// parameters to the remaining arguments. Without arguments, lists all variables and functions.
+pub fn set(
+    args: Vec<String>,
+    options: &mut Options,
+    variables: &mut Variables,
+    functions: &Functions,
+) -> Result<String> {
+    if args.is_empty() {
+        return Ok(listing(variables, functions));
+    }
+    let mut output = Vec::new();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next_if(|arg| arg.starts_with(['-', '+'])) {
+        match arg.as_str() {
+            "--" => {
+                variables.set_positional(args.by_ref().collect());
+                break;
+            }
+            // `set -` turns off tracing and ends the options, without emptying `$@`.
+            "-" => {
+                options.set_option("xtrace", false)?;
+                options.set_option("verbose", false)?;
+                break;
+            }
+            _ => {}
+        }
+        let on = arg.starts_with('-');
+        for flag in arg.chars().skip(1) {
+            if flag != 'o' {
+                options.set_flag(flag, on)?;
+                continue;
+            }
+            match args.next_if(|arg| !arg.starts_with(['-', '+'])) {
+                Some(name) => options.set_option(&name, on)?,
+                None if on => output.extend(
+                    options
+                        .options()
+                        .map(|(name, value)| format!("{:<15}\t{}", name, on_off(value))),
+                ),
+                None => output.extend(options.options().map(|(name, value)| {
+                    format!("set {}o {}", if value { '-' } else { '+' }, name)
+                })),
+            }
+        }
+    }
+    let positional: Vec<String> = args.collect();
+    if !positional.is_empty() {
+        variables.set_positional(positional);
+    }
+    Ok(output.join("\n"))
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+/// Sets (`-s`) or unsets (`-u`) optional shell behaviors, or lists them, in a reusable form with
+/// `-p`. With `-q` nothing is listed. Returns 1 when a listed name is off or unknown.
+pub fn shopt(args: Vec<String>, options: &mut Options) -> Result<i32> {
+    let mut value = None;
+    let mut print = false;
+    let mut quiet = false;
+    let mut args = args.into_iter().peekable();
+    while let Some(flags) = args.next_if(|arg| arg.len() > 1 && arg.starts_with('-')) {
+        if flags == "--" {
+            break;
+        }
+        for flag in flags.chars().skip(1) {
+            match flag {
+                's' | 'u' if value.is_some_and(|value| value != (flag == 's')) => {
+                    eprintln!("shopt: cannot set and unset shell options simultaneously");
+                    return Ok(1);
+                }
+                's' | 'u' => value = Some(flag == 's'),
+                'p' => print = true,
+                'q' => quiet = true,
+                _ => anyhow::bail!(
+                    "shopt: -{}: invalid option\nshopt: usage: shopt [-pqsu] [optname ...]",
+                    flag
+                ),
+            }
+        }
+    }
+    let names: Vec<String> = args.collect();
+    let mut status = 0;
+    for name in &names {
+        if !options.shopts().any(|(n, _)| n == name) {
+            eprintln!("shopt: {}: invalid shell option name", name);
+            status = 1;
+        }
+    }
+    if let Some(value) = value
+        && !names.is_empty()
+    {
+        for name in &names {
+            // Unknown names were already reported.
+            let _ = options.set_shopt(name, value);
+        }
+        return Ok(status);
+    }
+    // Named options are listed in the order given.
+    let listed: Vec<(&str, bool)> = match names.is_empty() {
+        true => options
+            .shopts()
+            .filter(|(_, enabled)| value.is_none_or(|value| *enabled == value))
+            .collect(),
+        false => names
+            .iter()
+            .filter_map(|name| options.shopts().find(|(n, _)| n == name))
+            .collect(),
+    };
+    let mut output = Vec::new();
+    for (name, enabled) in listed {
+        if !names.is_empty() && !enabled {
+            status = 1;
+        }
+        output.push(match print {
+            true => format!("shopt {} {}", if enabled { "-s" } else { "-u" }, name),
+            false => format!("{:<15}\t{}", name, on_off(enabled)),
+        });
+    }
+    if !quiet && !output.is_empty() {
+        // A closed pipe is not an error worth reporting.
+        let _ = writeln!(io::stdout(), "{}", output.join("\n"));
+    }
+    Ok(status)
+}
+
+/// Sets the commands run on signals and on the `EXIT`, `ERR`, `DEBUG` and `RETURN` conditions.
+/// An empty command ignores the signal and `-` resets it. `-p` prints the traps and `-l` lists
+/// the signal names.
+pub fn trap(args: Vec<String>, traps: &mut Traps) -> Result<i32> {
+    let mut print = false;
+    let mut list = false;
+    let mut args = args.into_iter().peekable();
+    while let Some(flags) = args.next_if(|arg| arg.len() > 1 && arg.starts_with('-')) {
+        if flags == "--" {
+            break;
+        }
+        for flag in flags.chars().skip(1) {
+            match flag {
+                'l' => list = true,
+                'p' => print = true,
+                _ => anyhow::bail!(
+                    "trap: -{}: invalid option\ntrap: usage: trap [-lp] [[action] signal_spec ...]",
+                    flag
+                ),
+            }
+        }
+    }
+    let mut args: Vec<String> = args.collect();
+    if list {
+        let _ = writeln!(io::stdout(), "{}", signal::listing());
+        return Ok(0);
+    }
+    // With a single operand, or a number first, the operands are all conditions to reset.
+    let action = match print || args.len() < 2 || args[0].parse::<u32>().is_ok() {
+        true => None,
+        false => Some(args.remove(0)).filter(|action| action != "-"),
+    };
+    let mut status = 0;
+    let mut conditions = Vec::new();
+    for spec in &args {
+        match Trap::parse(spec) {
+            Some(condition) => conditions.push(condition),
+            None => {
+                eprintln!("trap: {}: invalid signal specification", spec);
+                status = 1;
+            }
+        }
+    }
+    if print || args.is_empty() {
+        let printed: Vec<String> = traps
+            .iter()
+            .filter(|(condition, _)| args.is_empty() || conditions.contains(condition))
+            .map(|(condition, command)| {
+                format!("trap -- '{}' {}", command.replace('\'', r"'\''"), condition)
+            })
+            .collect();
+        if !printed.is_empty() {
+            let _ = writeln!(io::stdout(), "{}", printed.join("\n"));
+        }
+        return Ok(status);
+    }
+    for condition in conditions {
+        if let Trap::Signal(number) = condition {
+            match action.as_deref() {
+                None => signal::reset(number),
+                Some("") => signal::ignore(number),
+                Some(_) => signal::catch(number),
+            }
+        }
+        match &action {
+            Some(action) => traps.insert(condition, action.clone()),
+            None => traps.remove(&condition),
         };
     }
-    Ok("".to_string())
+    Ok(status)
+}
+
+/// Number of enclosing loops that `break` or `continue` applies to.
+pub fn loop_count(builtin: &Builtin, args: &[String], loop_depth: u32) -> Result<u32> {
+    anyhow::ensure!(
+        loop_depth > 0,
+        "{}: only meaningful in a `for', `while', or `until' loop",
+        builtin
+    );
+    let n = match args.first() {
+        Some(n) => n
+            .parse::<i64>()
+            .map_err(|_| anyhow::anyhow!("{}: {}: numeric argument required", builtin, n))?,
+        None => 1,
+    };
+    anyhow::ensure!(n > 0, "{}: {}: loop count out of range", builtin, n);
+    Ok(n.min(loop_depth as i64) as u32)
+}
+
+/// Evaluates `test` or `[`, whose last argument must be `]`.
+pub fn test(builtin: &Builtin, mut args: Vec<String>) -> Result<bool> {
+    if *builtin == Builtin::Bracket && args.pop().is_none_or(|last| last != "]") {
+        anyhow::bail!("[: missing `]'")
+    }
+    condition::test(&builtin.to_string(), &args)
+}
+
+/// Defines aliases given as `name=value`, or prints the named ones (all of them without names).
+pub fn alias(args: Vec<String>, aliases: &mut Aliases) -> Result<String> {
+    let format =
+        |name: &str, value: &str| format!("alias {}='{}'", name, value.replace('\'', "'\\''"));
+    let names: Vec<&String> = args.iter().filter(|arg| *arg != "-p").collect();
+    if names.is_empty() {
+        let output: Vec<String> = aliases
+            .iter()
+            .map(|(name, value)| format(name, value))
+            .collect();
+        return Ok(output.join("\n"));
+    }
+    let mut output = Vec::new();
+    for arg in names {
+        match arg.split_once('=') {
+            Some((name, value)) => {
+                anyhow::ensure!(
+                    !name.is_empty() && !name.contains(['/', '$', '`', '"', '\'', '\\']),
+                    "alias: `{}': invalid alias name",
+                    name
+                );
+                aliases.insert(name.to_string(), value.to_string());
+            }
+            None => match aliases.get(arg) {
+                Some(value) => output.push(format(arg, value)),
+                None => anyhow::bail!("alias: {}: not found", arg),
+            },
+        }
+    }
+    Ok(output.join("\n"))
+}
+
+pub fn unalias(args: Vec<String>, aliases: &mut Aliases) -> Result<String> {
+    if args.first().is_some_and(|arg| arg == "-a") {
+        aliases.clear();
+        return Ok(String::new());
+    }
+    anyhow::ensure!(
+        !args.is_empty(),
+        "unalias: usage: unalias [-a] name [name ...]"
+    );
+    for name in args {
+        if aliases.remove(&name).is_none() {
+            anyhow::bail!("unalias: {}: not found", name)
+        }
+    }
+    Ok(String::new())
+}
+
+/// Reads a line from standard input and splits it on `IFS` into the named variables, the last
+/// one taking the rest of the line. Returns the exit status: 1 at end of input and 142 when the
+/// `-t` timeout expires.
+pub fn read(args: Vec<String>, variables: &mut Variables) -> Result<i32> {
+    let mut raw = false;
+    let mut silent = false;
+    let mut prompt = None;
+    let mut delimiter = b'\n';
+    let mut nchars = None;
+    let mut timeout = None;
+    let mut array = None;
+    let mut names = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !names.is_empty() || !arg.starts_with('-') || arg == "-" {
+            names.push(arg);
+            continue;
+        }
+        for (i, flag) in arg.char_indices().skip(1) {
+            match flag {
+                'r' => raw = true,
+                's' => silent = true,
+                'p' | 'd' | 'n' | 't' | 'a' => {
+                    let value = match &arg[i + 1..] {
+                        "" => args.next().ok_or_else(|| {
+                            anyhow::anyhow!("read: -{}: option requires an argument", flag)
+                        })?,
+                        value => value.to_string(),
+                    };
+                    match flag {
+                        'p' => prompt = Some(value),
+                        'd' => delimiter = value.bytes().next().unwrap_or(b'\0'),
+                        'n' => {
+                            nchars =
+                                Some(value.parse::<usize>().map_err(|_| {
+                                    anyhow::anyhow!("read: {}: invalid number", value)
+                                })?)
+                        }
+                        't' => {
+                            timeout = Some(
+                                value
+                                    .parse::<f64>()
+                                    .ok()
+                                    .filter(|t| *t >= 0.0)
+                                    .map(Duration::from_secs_f64)
+                                    .ok_or_else(|| {
+                                        anyhow::anyhow!(
+                                            "read: {}: invalid timeout specification",
+                                            value
+                                        )
+                                    })?,
+                            )
+                        }
+                        _ => array = Some(value),
+                    }
+                    break;
+                }
+                _ => anyhow::bail!("read: -{}: invalid option", flag),
+            }
+        }
+    }
+    if let Some(name) = names.iter().find(|name| !is_name(name)) {
+        anyhow::bail!("read: `{}': not a valid identifier", name)
+    }
+
+    // SAFETY: `isatty` accepts any descriptor number.
+    let is_terminal = unsafe { libc::isatty(0) == 1 };
+    if timeout == Some(Duration::ZERO) {
+        // Only reports whether input is available.
+        return Ok(if wait_for_input(Some(Duration::ZERO)) {
+            0
+        } else {
+            1
+        });
+    }
+    if let Some(prompt) = prompt
+        && is_terminal
+    {
+        eprint!("{}", prompt);
+    }
+    let _mode = (is_terminal && (silent || nchars.is_some()))
+        .then(|| TerminalMode::set(!silent, nchars.is_none()))
+        .flatten();
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut line = LineBuffer::default();
+    let mut escaped = false;
+    let status = loop {
+        if nchars.is_some_and(|n| line.chars.len() >= n) {
+            break 0;
+        }
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if !wait_for_input(remaining) {
+            break 142;
+        }
+        let mut byte = 0u8;
+        // SAFETY: reads at most one byte into `byte`.
+        match unsafe { libc::read(0, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+            1 => {}
+            0 => break 1,
+            _ => {
+                let error = std::io::Error::last_os_error();
+                if error.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                anyhow::bail!("read: read error: {}", error)
+            }
+        }
+        if escaped {
+            escaped = false;
+            // A backslash-newline continues the line.
+            if byte != b'\n' {
+                line.push(byte, true);
+            }
+        } else if byte == delimiter {
+            break 0;
+        } else if byte == b'\\' && !raw {
+            escaped = true;
+        } else {
+            line.push(byte, false);
+        }
+    };
+    if silent && is_terminal {
+        eprintln!();
+    }
+
+    let chars = line.finish();
+    let assigned = if let Some(array) = array {
+        let fields = split_read_fields(&chars, &variables.ifs(), usize::MAX);
+        variables.set(
+            array,
+            Value::Indexed(fields.into_iter().enumerate().collect()),
+        )
+    } else if names.is_empty() {
+        variables.insert(
+            String::from("REPLY"),
+            chars.iter().map(|(c, _)| c).collect(),
+        )
+    } else {
+        let fields = split_read_fields(&chars, &variables.ifs(), names.len());
+        names.into_iter().enumerate().try_for_each(|(i, name)| {
+            variables.insert(name, fields.get(i).cloned().unwrap_or_default())
+        })
+    };
+    assigned.map_err(|e| anyhow::anyhow!("read: {}", e))?;
+    Ok(status)
+}
+
+/// Formats the arguments and writes them to standard output, or assigns them to a variable with
+/// `-v`. Returns 1 when an argument was not a valid number.
+pub fn printf(args: Vec<String>, variables: &mut Variables) -> Result<i32> {
+    let (name, args) = match args.split_first() {
+        Some((flag, rest)) if flag == "-v" => match rest.split_first() {
+            Some((name, rest)) if is_name(name) => (Some(name.clone()), rest),
+            Some((name, _)) => anyhow::bail!("printf: `{}': not a valid identifier", name),
+            None => anyhow::bail!("printf: -v: option requires an argument"),
+        },
+        Some((flag, rest)) if flag == "--" => (None, rest),
+        _ => (None, &args[..]),
+    };
+    let Some((format, args)) = args.split_first() else {
+        anyhow::bail!("printf: usage: printf [-v var] format [arguments]")
+    };
+    let formatted = printf::format(format, args);
+    for error in &formatted.errors {
+        eprintln!("{error}");
+    }
+    match name {
+        Some(name) => variables
+            .insert(name, formatted.output)
+            .map_err(|e| anyhow::anyhow!("printf: {}", e))?,
+        None => {
+            // A closed pipe is not an error worth reporting.
+            let _ = io::stdout().write_all(formatted.output.as_bytes());
+        }
+    }
+    Ok(i32::from(!formatted.errors.is_empty()))
+}
+
+/// Waits until standard input is readable, returning false if the timeout expires first.
+fn wait_for_input(timeout: Option<Duration>) -> bool {
+    let timeout = timeout.map_or(-1, |timeout| {
+        timeout.as_millis().min(i32::MAX as u128) as i32
+    });
+    let mut poll = libc::pollfd {
+        fd: 0,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        // SAFETY: `poll` points to a single valid pollfd.
+        match unsafe { libc::poll(&mut poll, 1, timeout) } {
+            -1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {}
+            0 => return false,
+            _ => return true,
+        }
+    }
+}
+
+/// Characters read by `read`, each marked with whether it was escaped by a backslash.
+#[derive(Default)]
+struct LineBuffer {
+    chars: Vec<(char, bool)>,
+    pending: Vec<u8>, // bytes of an incomplete UTF-8 sequence
+}
+
+impl LineBuffer {
+    fn push(&mut self, byte: u8, escaped: bool) {
+        self.pending.push(byte);
+        match std::str::from_utf8(&self.pending) {
+            Ok(text) => {
+                self.chars.extend(text.chars().map(|c| (c, escaped)));
+                self.pending.clear();
+            }
+            Err(e) if e.error_len().is_some() => self.flush(escaped),
+            Err(_) => {}
+        }
+    }
+
+    fn flush(&mut self, escaped: bool) {
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.chars.extend(text.chars().map(|c| (c, escaped)));
+        self.pending.clear();
+    }
+
+    fn finish(mut self) -> Vec<(char, bool)> {
+        self.flush(false);
+        self.chars
+    }
+}
+
+/// Splits a line read by `read` into at most `count` fields on unescaped `IFS` characters. The
+/// last field gets the rest of the line, without its trailing IFS whitespace.
+fn split_read_fields(chars: &[(char, bool)], ifs: &str, count: usize) -> Vec<String> {
+    let is_ifs = |i: usize| !chars[i].1 && ifs.contains(chars[i].0);
+    let is_space = |i: usize| is_ifs(i) && " \t\n".contains(chars[i].0);
+    let text = |range: &[(char, bool)]| range.iter().map(|(c, _)| c).collect::<String>();
+    let mut fields = Vec::new();
+    let mut i = 0;
+    while i < chars.len() && is_space(i) {
+        i += 1;
+    }
+    while fields.len() + 1 < count && i < chars.len() {
+        let start = i;
+        while i < chars.len() && !is_ifs(i) {
+            i += 1;
+        }
+        fields.push(text(&chars[start..i]));
+        while i < chars.len() && is_space(i) {
+            i += 1;
+        }
+        if i < chars.len() && is_ifs(i) {
+            i += 1;
+            while i < chars.len() && is_space(i) {
+                i += 1;
+            }
+        }
+    }
+    let mut end = chars.len();
+    while end > i && is_space(end - 1) {
+        end -= 1;
+    }
+    if i < end {
+        fields.push(text(&chars[i..end]));
+    }
+    fields
+}
+
+/// Terminal settings changed for `read -s` or `read -n`, restored when dropped.
+struct TerminalMode {
+    original: libc::termios,
+}
+
+impl TerminalMode {
+    fn set(echo: bool, canonical: bool) -> Option<Self> {
+        // SAFETY: `termios` is plain data, filled in by `tcgetattr` before use.
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(0, &mut original) != 0 {
+                return None;
+            }
+            let mut mode = original;
+            if !echo {
+                mode.c_lflag &= !libc::ECHO;
+            }
+            if !canonical {
+                mode.c_lflag &= !libc::ICANON;
+                mode.c_cc[libc::VMIN] = 1;
+                mode.c_cc[libc::VTIME] = 0;
+            }
+            libc::tcsetattr(0, libc::TCSANOW, &mode);
+            Some(TerminalMode { original })
+        }
+    }
+}
+
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read by `tcgetattr`.
+        unsafe { libc::tcsetattr(0, libc::TCSANOW, &self.original) };
+    }
 }
@@ -1,133 +1,218 @@
 use std::{
-    fs::{File, metadata},
-    io::{self, PipeReader},
+    fs::{self, metadata},
+    io::{self, Write},
     os::unix::fs::PermissionsExt,
     path::Path,
-    process::{Command, Stdio},
 };
 
-use crate::job::{Job, JobStatus};
+/// Forks the shell, returning the child's pid in the parent and `None` in the child.
+pub fn fork() -> io::Result<Option<u32>> {
+    // Anything still buffered would otherwise be written by both processes.
+    io::stdout().flush()?;
+    io::stderr().flush()?;
+    // SAFETY: the shell is single-threaded, so the child starts from a consistent state.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(None),
+        pid => Ok(Some(pid as u32)),
+    }
+}
 
-#[derive(Debug)]
-pub struct ShellCommand {
-    pub name: String,
-    pub args: Vec<String>,
-    pub stdout_file: Option<File>,
-    pub stderr_file: Option<File>,
-    pub is_background_job: bool,
+/// Terminates a forked child without running the parent's cleanup.
+pub fn exit_child(code: i32) -> ! {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+    // SAFETY: `_exit` only ends the process.
+    unsafe { libc::_exit(code) }
 }
 
-impl ShellCommand {
-    pub fn new(
-        name: String,
-        args: Vec<String>,
-        stdout_file: Option<File>,
-        stderr_file: Option<File>,
-        is_background_job: bool,
-    ) -> Self {
-        Self {
-            name,
-            args,
-            stdout_file,
-            stderr_file,
-            is_background_job,
+/// Waits for the process to exit and returns its status code.
+pub fn wait_pid(pid: u32) -> io::Result<i32> {
+    let mut status = 0;
+    loop {
+        // SAFETY: `status` is a valid pointer for the duration of the call.
+        if unsafe { libc::waitpid(pid as libc::pid_t, &mut status, 0) } >= 0 {
+            return Ok(status_code(status));
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
         }
     }
+}
 
-    pub fn run_as_excutable(
-        self,
-        stdin: Option<PipeReader>,
-        is_last: bool,
-    ) -> anyhow::Result<Option<PipeReader>> {
-        let stdin = if let Some(stdio) = stdin {
-            Stdio::from(stdio)
-        } else {
-            Stdio::inherit()
-        };
+/// Sends a signal to a process.
+pub fn send_signal(pid: u32, signal: i32) -> io::Result<()> {
+    // SAFETY: `kill` has no memory safety requirements.
+    match unsafe { libc::kill(pid as libc::pid_t, signal) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
 
-        let mut output = None;
+/// Returns the status code if the process has exited, without blocking.
+pub fn try_wait_pid(pid: u32) -> io::Result<Option<i32>> {
+    let mut status = 0;
+    // SAFETY: `status` is a valid pointer for the duration of the call.
+    match unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(None),
+        _ => Ok(Some(status_code(status))),
+    }
+}
 
-        let stdout = if let Some(stdout_file) = self.stdout_file {
-            Stdio::from(stdout_file)
-        } else if !is_last {
-            let (stdout_reader, stdout_writer) = io::pipe()?;
-            output = Some(stdout_reader);
-            Stdio::from(stdout_writer)
-        } else {
-            Stdio::inherit()
-        };
+/// Converts a wait status into a shell status code (128 + N when killed by signal N).
+fn status_code(status: libc::c_int) -> i32 {
+    if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        libc::WEXITSTATUS(status)
+    }
+}
 
-        let stderr = if let Some(stderr_file) = self.stderr_file {
-            Stdio::from(stderr_file)
-        } else {
-            Stdio::inherit()
-        };
+/// An I/O error message without the `(os error N)` suffix.
+pub fn io_error_message(error: &io::Error) -> String {
+    let message = error.to_string();
+    match message.split_once(" (os error") {
+        Some((message, _)) => message.to_string(),
+        None => message,
+    }
+}
 
-        let mut child = Command::new(&self.name)
-            .args(&self.args)
-            .stdin(stdin)
-            .stdout(stdout)
-            .stderr(stderr)
-            .spawn()?;
+/// File descriptors replaced by redirections. The originals are restored when dropped.
+pub struct SavedFds {
+    saved: Vec<(i32, Option<i32>)>, // redirected fd and a copy of the original, if it was open
+}
+
+impl SavedFds {
+    pub fn new() -> Self {
+        SavedFds { saved: Vec::new() }
+    }
 
-        if is_last && !self.is_background_job {
-            child.wait()?;
+    fn save(&mut self, fd: i32) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        if self.saved.iter().all(|(saved_fd, _)| *saved_fd != fd) {
+            // SAFETY: duplicating a descriptor has no memory-safety requirements.
+            let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+            self.saved.push((fd, (copy >= 0).then_some(copy)));
         }
+    }
 
-        Ok(output)
+    /// Makes `fd` refer to the same open file as `source`.
+    pub fn redirect(&mut self, fd: i32, source: i32) -> io::Result<()> {
+        self.save(fd);
+        // SAFETY: `dup2` only manipulates the descriptor table.
+        if unsafe { libc::dup2(source, fd) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
     }
 
-    pub fn run_as_background_job(
-        self,
-        stdin: Option<PipeReader>,
-        number: u32,
-    ) -> anyhow::Result<Job> {
-        let stdin = if let Some(stdio) = stdin {
-            Stdio::from(stdio)
-        } else {
-            Stdio::inherit()
-        };
+    pub fn close(&mut self, fd: i32) {
+        self.save(fd);
+        // SAFETY: closing a descriptor has no memory-safety requirements.
+        unsafe { libc::close(fd) };
+    }
+}
 
-        let stdout = if let Some(stdout_file) = self.stdout_file {
-            Stdio::from(stdout_file)
-        } else {
-            Stdio::inherit()
-        };
+impl Drop for SavedFds {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+        for (fd, copy) in self.saved.drain(..).rev() {
+            // SAFETY: `copy` was duplicated from `fd` by `save` and is owned here.
+            unsafe {
+                match copy {
+                    Some(copy) => {
+                        libc::dup2(copy, fd);
+                        libc::close(copy);
+                    }
+                    None => {
+                        libc::close(fd);
+                    }
+                }
+            }
+        }
+    }
+}
 
-        let stderr = if let Some(stderr_file) = self.stderr_file {
-            Stdio::from(stderr_file)
-        } else {
-            Stdio::inherit()
-        };
+/// The closest of `candidates` to a mistyped command name, if any is close enough.
+pub fn similar_command<'a>(
+    name: &str,
+    candidates: impl Iterator<Item = &'a str>,
+) -> Option<&'a str> {
+    // Short names allow fewer mistakes, so that suggestions stay plausible.
+    let allowed = name.chars().count().div_ceil(3).min(2);
+    candidates
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= allowed)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
 
-        let child = Command::new(&self.name)
-            .args(&self.args)
-            .stdin(stdin)
-            .stdout(stdout)
-            .stderr(stderr)
-            .spawn()?;
+/// Number of inserted, deleted, replaced or swapped adjacent characters that turn `a` into `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] is the distance between the first i chars of a and the first j of b.
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let replaced = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = replaced
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
 
-        Ok(Job {
-            child,
-            number,
-            command: format!("{} {}", self.name, self.args.join(" ")),
-            status: JobStatus::Running,
-        })
+/// Names of the executable files in the `PATH` directories.
+pub fn path_executables() -> Vec<String> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let mut names = Vec::new();
+    for dir in std::env::split_paths(&path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            if is_excutable(&entry.path())
+                && let Some(name) = entry.file_name().to_str()
+            {
+                names.push(name.to_string());
+            }
+        }
     }
+    names
 }
 
 pub fn find_excutable(cmd_name: &str) -> Option<String> {
+    if cmd_name.contains('/') {
+        return is_excutable(Path::new(cmd_name)).then(|| cmd_name.to_string());
+    }
     let path = std::env::var_os("PATH").expect("PATH not found");
     for dir in std::env::split_paths(&path) {
         let p = format!("{}/{}", dir.display(), cmd_name);
-        let path = Path::new(&p);
-        if path.is_file()
-            && let Ok(metadata) = metadata(path)
-            && let mode = metadata.permissions().mode()
-            && (mode & 0o100 != 0 || mode & 0o010 != 0 || mode & 0o001 != 0)
-        {
+        if is_excutable(Path::new(&p)) {
             return Some(p);
         }
     }
     None
 }
+
+fn is_excutable(path: &Path) -> bool {
+    path.is_file()
+        && metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
@@ -0,0 +1,210 @@
+use std::{
+    ffi::CString,
+    fs::{self, Metadata},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+};
+
+use anyhow::Result;
+
+const UNARY_OPERATORS: [&str; 22] = [
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-L", "-n", "-O", "-G", "-p", "-r", "-s",
+    "-S", "-t", "-u", "-w", "-x", "-z",
+];
+
+const BINARY_OPERATORS: [&str; 14] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+pub fn is_unary_operator(op: &str) -> bool {
+    UNARY_OPERATORS.contains(&op)
+}
+
+pub fn is_binary_operator(op: &str) -> bool {
+    BINARY_OPERATORS.contains(&op)
+}
+
+/// Evaluates the arguments of `test`, or of `[` without its closing `]`.
+pub fn test(name: &str, args: &[String]) -> Result<bool> {
+    // A lone argument is a string test, even when it looks like an operator.
+    match args {
+        [] => return Ok(false),
+        [arg] => return Ok(!arg.is_empty()),
+        _ => {}
+    }
+    let mut parser = TestParser { name, args, pos: 0 };
+    let result = parser.parse_or()?;
+    if let Some(arg) = args.get(parser.pos) {
+        anyhow::bail!("{}: {}: unexpected argument", name, arg)
+    }
+    Ok(result)
+}
+
+struct TestParser<'a> {
+    name: &'a str,
+    args: &'a [String],
+    pos: usize,
+}
+
+impl TestParser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.args.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<&str> {
+        let arg = self.args.get(self.pos);
+        self.pos += 1;
+        match arg {
+            Some(arg) => Ok(arg),
+            None => anyhow::bail!("{}: argument expected", self.name),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<bool> {
+        let mut result = self.parse_and()?;
+        while self.peek() == Some("-o") {
+            self.pos += 1;
+            result |= self.parse_and()?;
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self) -> Result<bool> {
+        let mut result = self.parse_not()?;
+        while self.peek() == Some("-a") {
+            self.pos += 1;
+            result &= self.parse_not()?;
+        }
+        Ok(result)
+    }
+
+    fn parse_not(&mut self) -> Result<bool> {
+        if self.peek() == Some("!") && self.pos + 1 < self.args.len() {
+            self.pos += 1;
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<bool> {
+        let remaining = self.args.len() - self.pos;
+        // Binary operators win over everything else, so `[ "(" = "(" ]` compares strings.
+        if remaining >= 3 && is_binary_operator(&self.args[self.pos + 1]) {
+            let lhs = self.next()?.to_string();
+            let op = self.next()?.to_string();
+            let rhs = self.next()?.to_string();
+            return binary_test(self.name, &lhs, &op, &rhs);
+        }
+        let arg = self.next()?.to_string();
+        if arg == "(" {
+            let result = self.parse_or()?;
+            if self.next()? != ")" {
+                anyhow::bail!("{}: `)' expected", self.name)
+            }
+            return Ok(result);
+        }
+        if is_unary_operator(&arg) && self.peek().is_some() {
+            let operand = self.next()?.to_string();
+            return unary_test(&arg, &operand);
+        }
+        Ok(!arg.is_empty())
+    }
+}
+
+/// Evaluates a unary string or file test such as `-n str` or `-d path`.
+pub fn unary_test(op: &str, operand: &str) -> Result<bool> {
+    let metadata = || fs::metadata(operand).ok();
+    let has_mode = |mask: u32| metadata().is_some_and(|m| m.mode() & mask != 0);
+    let result = match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-a" | "-e" => metadata().is_some(),
+        "-f" => metadata().is_some_and(|m| m.is_file()),
+        "-d" => metadata().is_some_and(|m| m.is_dir()),
+        "-b" => metadata().is_some_and(|m| m.file_type().is_block_device()),
+        "-c" => metadata().is_some_and(|m| m.file_type().is_char_device()),
+        "-p" => metadata().is_some_and(|m| m.file_type().is_fifo()),
+        "-S" => metadata().is_some_and(|m| m.file_type().is_socket()),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|m| m.file_type().is_symlink()),
+        "-s" => metadata().is_some_and(|m| m.len() > 0),
+        "-g" => has_mode(0o2000),
+        "-u" => has_mode(0o4000),
+        "-k" => has_mode(0o1000),
+        // SAFETY: `geteuid` and `getegid` cannot fail.
+        "-O" => metadata().is_some_and(|m| m.uid() == unsafe { libc::geteuid() }),
+        "-G" => metadata().is_some_and(|m| m.gid() == unsafe { libc::getegid() }),
+        "-r" => access(operand, libc::R_OK),
+        "-w" => access(operand, libc::W_OK),
+        "-x" => access(operand, libc::X_OK),
+        "-t" => {
+            let fd: i32 = operand
+                .trim()
+                .parse()
+                .map_err(|_| anyhow::anyhow!("{}: integer expression expected", operand))?;
+            // SAFETY: `isatty` accepts any descriptor number.
+            unsafe { libc::isatty(fd) == 1 }
+        }
+        _ => anyhow::bail!("{}: unary operator expected", op),
+    };
+    Ok(result)
+}
+
+/// Evaluates a binary string, integer or file comparison as `test` does.
+fn binary_test(name: &str, lhs: &str, op: &str, rhs: &str) -> Result<bool> {
+    let integer = |operand: &str| {
+        operand
+            .trim()
+            .parse::<i64>()
+            .map_err(|_| anyhow::anyhow!("{}: {}: integer expression expected", name, operand))
+    };
+    let result = match op {
+        "=" | "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        ">" => lhs > rhs,
+        "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+            integer_test(integer(lhs)?, op, integer(rhs)?)
+        }
+        _ => file_test(lhs, op, rhs),
+    };
+    Ok(result)
+}
+
+pub fn integer_test(lhs: i64, op: &str, rhs: i64) -> bool {
+    match op {
+        "-eq" => lhs == rhs,
+        "-ne" => lhs != rhs,
+        "-lt" => lhs < rhs,
+        "-le" => lhs <= rhs,
+        "-gt" => lhs > rhs,
+        _ => lhs >= rhs,
+    }
+}
+
+/// Compares two files with `-nt`, `-ot` or `-ef`.
+pub fn file_test(lhs: &str, op: &str, rhs: &str) -> bool {
+    let modified = |m: Metadata| m.modified().ok();
+    let lhs_metadata = fs::metadata(lhs).ok();
+    let rhs_metadata = fs::metadata(rhs).ok();
+    match op {
+        "-nt" => match (lhs_metadata, rhs_metadata) {
+            (Some(lhs), Some(rhs)) => modified(lhs) > modified(rhs),
+            (lhs, _) => lhs.is_some(),
+        },
+        "-ot" => match (lhs_metadata, rhs_metadata) {
+            (Some(lhs), Some(rhs)) => modified(lhs) < modified(rhs),
+            (_, rhs) => rhs.is_some(),
+        },
+        _ => match (lhs_metadata, rhs_metadata) {
+            (Some(lhs), Some(rhs)) => lhs.dev() == rhs.dev() && lhs.ino() == rhs.ino(),
+            _ => false,
+        },
+    }
+}
+
+fn access(path: &str, mode: libc::c_int) -> bool {
+    let Ok(path) = CString::new(path) else {
+        return false;
+    };
+    // SAFETY: `path` is a valid NUL-terminated string.
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
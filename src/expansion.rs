@@ -0,0 +1,764 @@
+use std::{
+    env,
+    ffi::{CStr, CString},
+    fs,
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::{
+    arithmetic,
+    lexer::{Quoting, Word},
+    options::Options,
+    pattern::{PatternChar, has_wildcards, lowercase, pattern_from_word, pattern_matches},
+    variable::{Unbound, Value, Variables},
+};
+
+/// Expands words into the final list of arguments: brace expansion, tilde expansion, parameter
+/// expansion, field splitting, pathname expansion and quote removal.
+pub fn expand_words(
+    words: &[Word],
+    variables: &mut Variables,
+    options: &Options,
+) -> Result<Vec<String>> {
+    let ifs = variables.ifs();
+    let mut res = Vec::new();
+    for word in words.iter().flat_map(brace_expansion) {
+        let word = tilde_expansion(&word, variables, false);
+        for field in parameter_expansion(&word, variables)? {
+            for field in split_field(field, &ifs) {
+                res.extend(pathname_expansion(&field, options)?);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Expands a word into a single string without field splitting, as for assignment values.
+pub fn expand_word(word: &Word, variables: &mut Variables) -> Result<String> {
+    let word = tilde_expansion(word, variables, true);
+    let fields: Vec<String> = parameter_expansion(&word, variables)?
+        .iter()
+        .map(Word::text)
+        .collect();
+    Ok(fields.join(" "))
+}
+
+/// Expands a `case` pattern, keeping track of which characters were quoted.
+pub fn expand_pattern(word: &Word, variables: &mut Variables) -> Result<Vec<PatternChar>> {
+    let word = tilde_expansion(word, variables, false);
+    let fields: Vec<Vec<PatternChar>> = parameter_expansion(&word, variables)?
+        .iter()
+        .map(pattern_from_word)
+        .collect();
+    Ok(fields.join(&(' ', true)))
+}
+
+/// Expands the parameters of an arithmetic expression and evaluates it.
+pub fn expand_arithmetic(expression: &str, variables: &mut Variables) -> Result<i64> {
+    let expression = expand_text(expression, variables)?;
+    arithmetic::evaluate(&expression, variables)
+}
+
+/// Expands an array subscript into a key: the text of an associative array key, or else the
+/// value of an arithmetic index.
+pub fn expand_subscript(name: &str, subscript: &str, variables: &mut Variables) -> Result<String> {
+    if variables.is_associative(name) {
+        expand_text(subscript, variables)
+    } else {
+        Ok(expand_arithmetic(subscript, variables)?.to_string())
+    }
+}
+
+/// Expands the parameters of text as if it were in double quotes.
+fn expand_text(text: &str, variables: &mut Variables) -> Result<String> {
+    let word = Word {
+        chars: text.chars().map(|c| (c, Quoting::Double)).collect(),
+        quoted: true,
+    };
+    let fields: Vec<String> = parameter_expansion(&word, variables)?
+        .iter()
+        .map(Word::text)
+        .collect();
+    Ok(fields.join(" "))
+}
+
+/// Expands the first unquoted `{a,b}` list or `{x..y[..step]}` sequence of a word, then the rest
+/// of each resulting word. Braces that form neither are left as they are.
+fn brace_expansion(word: &Word) -> Vec<Word> {
+    let chars = &word.chars;
+    let unquoted = |i: usize, c: char| chars.get(i) == Some(&(c, Quoting::Unquoted));
+    let mut start = 0;
+    while start < chars.len() {
+        if unquoted(start, '$') && unquoted(start + 1, '{') {
+            // Skip over a `${...}` parameter.
+            start = matching_brace(chars, start + 1).map_or(chars.len(), |end| end + 1);
+            continue;
+        }
+        if !unquoted(start, '{') {
+            start += 1;
+            continue;
+        }
+        let Some(end) = matching_brace(chars, start) else {
+            break;
+        };
+        let mut alternatives = Vec::new();
+        let mut depth = 0;
+        let mut item_start = start + 1;
+        for i in start + 1..end {
+            match chars[i] {
+                ('{', Quoting::Unquoted) => depth += 1,
+                ('}', Quoting::Unquoted) => depth -= 1,
+                (',', Quoting::Unquoted) if depth == 0 => {
+                    alternatives.push(chars[item_start..i].to_vec());
+                    item_start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        if alternatives.is_empty() {
+            match brace_sequence(&chars[start + 1..end]) {
+                Some(sequence) => {
+                    alternatives = sequence
+                        .into_iter()
+                        .map(|item| item.chars().map(|c| (c, Quoting::Unquoted)).collect())
+                        .collect();
+                }
+                None => {
+                    start += 1;
+                    continue;
+                }
+            }
+        } else {
+            alternatives.push(chars[item_start..end].to_vec());
+        }
+        return alternatives
+            .into_iter()
+            .flat_map(|alternative| {
+                let mut expanded = chars[..start].to_vec();
+                expanded.extend(alternative);
+                expanded.extend_from_slice(&chars[end + 1..]);
+                brace_expansion(&Word {
+                    chars: expanded,
+                    quoted: word.quoted,
+                })
+            })
+            .collect();
+    }
+    vec![word.clone()]
+}
+
+/// Index of the unquoted `}` closing the `{` at `start`.
+fn matching_brace(chars: &[(char, Quoting)], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, &(c, quoting)) in chars.iter().enumerate().skip(start) {
+        match (c, quoting) {
+            ('{', Quoting::Unquoted) => depth += 1,
+            ('}', Quoting::Unquoted) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Items of a `x..y[..step]` sequence of integers or single characters. Integers are padded
+/// with zeros when either end has a leading zero.
+fn brace_sequence(chars: &[(char, Quoting)]) -> Option<Vec<String>> {
+    if chars
+        .iter()
+        .any(|(_, quoting)| *quoting != Quoting::Unquoted)
+    {
+        return None;
+    }
+    let text: String = chars.iter().map(|(c, _)| c).collect();
+    let parts: Vec<&str> = text.split("..").collect();
+    let step = match parts.len() {
+        2 => 1,
+        3 => parts[2].parse::<i64>().ok()?.unsigned_abs().max(1),
+        _ => return None,
+    };
+    let (first, last) = (parts[0], parts[1]);
+    if let (Ok(from), Ok(to)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let has_leading_zero = |s: &str| {
+            s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0')
+        };
+        let width = if has_leading_zero(first) || has_leading_zero(last) {
+            first.len().max(last.len())
+        } else {
+            0
+        };
+        let items = sequence(from, to, step)
+            .map(|n| {
+                if n < 0 {
+                    format!("-{:0>width$}", -n, width = width.saturating_sub(1))
+                } else {
+                    format!("{:0>width$}", n)
+                }
+            })
+            .collect();
+        return Some(items);
+    }
+    let (mut first_chars, mut last_chars) = (first.chars(), last.chars());
+    match (
+        first_chars.next(),
+        first_chars.next(),
+        last_chars.next(),
+        last_chars.next(),
+    ) {
+        (Some(from), None, Some(to), None) if from.is_ascii() && to.is_ascii() => Some(
+            sequence(from as i64, to as i64, step)
+                .map(|n| char::from(n as u8).to_string())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn sequence(from: i64, to: i64, step: u64) -> impl Iterator<Item = i64> {
+    let count = from.abs_diff(to) / step + 1;
+    let step = if from <= to {
+        step as i64
+    } else {
+        -(step as i64)
+    };
+    (0..count as i64).map(move |i| from + i * step)
+}
+
+/// Replaces an unquoted `~`, `~user`, `~+` or `~-` prefix of a word with the corresponding
+/// directory. In assignment values, prefixes following a `:` are expanded as well.
+fn tilde_expansion(word: &Word, variables: &Variables, is_assignment: bool) -> Word {
+    let mut expanded = Word {
+        chars: Vec::with_capacity(word.chars.len()),
+        quoted: word.quoted,
+    };
+    let mut i = 0;
+    while i < word.chars.len() {
+        let at_prefix_start =
+            i == 0 || (is_assignment && word.chars[i - 1] == (':', Quoting::Unquoted));
+        if at_prefix_start && word.chars[i] == ('~', Quoting::Unquoted) {
+            let end = word.chars[i..]
+                .iter()
+                .position(|&(c, _)| c == '/' || (is_assignment && c == ':'))
+                .map_or(word.chars.len(), |len| i + len);
+            let prefix = &word.chars[i + 1..end];
+            if prefix.iter().all(|(_, q)| *q == Quoting::Unquoted) {
+                let user: String = prefix.iter().map(|(c, _)| c).collect();
+                if let Some(dir) = tilde_directory(&user, variables) {
+                    dir.chars().for_each(|c| expanded.push(c, Quoting::Literal));
+                    i = end;
+                    continue;
+                }
+            }
+        }
+        expanded.chars.push(word.chars[i]);
+        i += 1;
+    }
+    expanded
+}
+
+fn tilde_directory(prefix: &str, variables: &Variables) -> Option<String> {
+    match prefix {
+        "" => variables
+            .parameter("HOME")
+            .or_else(|| env::home_dir().map(|dir| dir.display().to_string())),
+        "+" => variables
+            .parameter("PWD")
+            .or_else(|| env::current_dir().ok().map(|dir| dir.display().to_string())),
+        "-" => variables.parameter("OLDPWD"),
+        user => stack_directory(user, variables).or_else(|| user_home(user)),
+    }
+}
+
+/// Entry of the directory stack for `~N` or `~+N`, counting from the top, or `~-N`, from the
+/// bottom.
+fn stack_directory(prefix: &str, variables: &Variables) -> Option<String> {
+    let digits = prefix.strip_prefix(['+', '-']).unwrap_or(prefix);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let n: usize = digits.parse().ok()?;
+    let stack = variables.directory_stack();
+    let index = match prefix.starts_with('-') {
+        true => stack.len().checked_sub(n + 1)?,
+        false => n,
+    };
+    stack.get(index).cloned()
+}
+
+/// Home directory of `user` from the passwd database.
+fn user_home(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    // SAFETY: `getpwnam` returns null or a pointer to a static passwd record that stays valid
+    // until the next passwd lookup, and its home directory is copied out right away.
+    unsafe {
+        let passwd = libc::getpwnam(name.as_ptr());
+        if passwd.is_null() || (*passwd).pw_dir.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr((*passwd).pw_dir)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+fn parameter_expansion(word: &Word, variables: &mut Variables) -> Result<Vec<Word>> {
+    let mut fields = Vec::new();
+    let mut field = Word {
+        chars: Vec::new(),
+        quoted: word.quoted,
+    };
+    let mut empty_list = false; // a quoted list such as "$@" expanded to no values
+    let mut i = 0;
+    while i < word.chars.len() {
+        let (c, quoting) = word.chars[i];
+        if c == '$' && quoting != Quoting::Literal {
+            if let Some((expression, end)) = arithmetic_at(&word.chars, i) {
+                let value = expand_arithmetic(&expression, variables)?;
+                let result_quoting = match quoting {
+                    Quoting::Double => Quoting::Double,
+                    _ => Quoting::Expanded,
+                };
+                value
+                    .to_string()
+                    .chars()
+                    .for_each(|c| field.push(c, result_quoting));
+                i = end;
+                continue;
+            }
+            let Some((text, end)) = parameter_at(&word.chars, i) else {
+                field.push(c, quoting);
+                i += 1;
+                continue;
+            };
+            let Some(reference) = Reference::parse(&text) else {
+                anyhow::bail!("${{{}}}: bad substitution", text)
+            };
+            i = end;
+            let result_quoting = match quoting {
+                Quoting::Double => Quoting::Double,
+                _ => Quoting::Expanded,
+            };
+            match expand_reference(&reference, variables)? {
+                Expanded::List(values) if reference.is_star() && quoting == Quoting::Double => {
+                    let separator = variables.ifs().chars().next().map(String::from);
+                    let value = values.join(separator.as_deref().unwrap_or(""));
+                    value.chars().for_each(|c| field.push(c, result_quoting));
+                }
+                Expanded::List(values) => {
+                    empty_list |= values.is_empty() && quoting == Quoting::Double;
+                    // Every value becomes a field of its own.
+                    if let Some((first, rest)) = values.split_first() {
+                        first.chars().for_each(|c| field.push(c, result_quoting));
+                        for value in rest {
+                            let next = Word {
+                                chars: Vec::new(),
+                                quoted: word.quoted,
+                            };
+                            fields.push(std::mem::replace(&mut field, next));
+                            value.chars().for_each(|c| field.push(c, result_quoting));
+                        }
+                    }
+                }
+                Expanded::Value(Some(value)) => {
+                    value.chars().for_each(|c| field.push(c, result_quoting));
+                }
+                Expanded::Value(None) => {}
+            }
+        } else {
+            field.push(c, quoting);
+            i += 1;
+        }
+    }
+    // A lone "$@" disappears entirely when there are no positional parameters.
+    if empty_list && fields.is_empty() && field.chars.is_empty() {
+        return Ok(Vec::new());
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// A parameter reference such as `$name`, `${#name}`, `${!name[@]}` or `${name[@]:1:2}`.
+struct Reference {
+    name: String,
+    subscript: Option<String>,
+    length: bool,                            // `${#...}`
+    keys: bool,                              // `${!name[@]}`
+    slice: Option<(String, Option<String>)>, // `${...:offset:length}`
+}
+
+/// The result of a parameter reference: a single value, or a list of values that each become a
+/// field, as from `$@` or `${name[@]}`.
+enum Expanded {
+    Value(Option<String>),
+    List(Vec<String>),
+}
+
+impl Reference {
+    fn parse(text: &str) -> Option<Self> {
+        let (length, keys, rest) = match text.split_at_checked(1) {
+            Some(("#", rest)) if !rest.is_empty() => (true, false, rest),
+            Some(("!", rest)) if !rest.is_empty() => (false, true, rest),
+            _ => (false, false, text),
+        };
+        let name_len = match rest.chars().next()? {
+            c if c.is_ascii_alphabetic() || c == '_' => rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len()),
+            c if c.is_ascii_digit() => rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len()),
+            c if "$!#?-@*".contains(c) => 1,
+            _ => return None,
+        };
+        let (name, mut rest) = rest.split_at(name_len);
+        let mut subscript = None;
+        if rest.starts_with('[') {
+            let mut depth = 0;
+            let end = rest.find(|c| {
+                match c {
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })?;
+            subscript = Some(rest[1..end].to_string());
+            rest = &rest[end + 1..];
+        }
+        let mut slice = None;
+        if let Some(spec) = rest.strip_prefix(':')
+            && !spec.starts_with(['-', '=', '?', '+'])
+        {
+            slice = Some(match spec.split_once(':') {
+                Some((offset, length)) => (offset.to_string(), Some(length.to_string())),
+                None => (spec.to_string(), None),
+            });
+            rest = "";
+        }
+        let is_list = matches!(subscript.as_deref(), Some("@" | "*"));
+        if !rest.is_empty() || (keys && !is_list) || ((length || keys) && slice.is_some()) {
+            return None;
+        }
+        Some(Self {
+            name: name.to_string(),
+            subscript,
+            length,
+            keys,
+            slice,
+        })
+    }
+
+    /// Whether the values are joined into one field when quoted, as for `"$*"`.
+    fn is_star(&self) -> bool {
+        match &self.subscript {
+            Some(subscript) => subscript == "*",
+            None => self.name == "*",
+        }
+    }
+}
+
+fn expand_reference(reference: &Reference, variables: &mut Variables) -> Result<Expanded> {
+    let name = &reference.name;
+    let list = if reference.keys {
+        Some(variables.get(name).map(Value::keys).unwrap_or_default())
+    } else if matches!(reference.subscript.as_deref(), Some("@" | "*")) {
+        Some(variables.get(name).map(Value::values).unwrap_or_default())
+    } else if reference.subscript.is_none() && (name == "@" || name == "*") {
+        let mut values = variables.positional().to_vec();
+        // Slices of the positional parameters count `$0` as the first.
+        if reference.slice.is_some() {
+            values.insert(0, variables.parameter("0").unwrap_or_default());
+        }
+        Some(values)
+    } else {
+        None
+    };
+    if let Some(list) = list {
+        if reference.length {
+            return Ok(Expanded::Value(Some(list.len().to_string())));
+        }
+        let list = match &reference.slice {
+            Some((offset, length)) => {
+                let (start, end) = slice_range(list.len(), offset, length, variables)?;
+                list[start..end].to_vec()
+            }
+            None => list,
+        };
+        return Ok(Expanded::List(list));
+    }
+    let value = match &reference.subscript {
+        Some(subscript) => {
+            let key = expand_subscript(name, subscript, variables)?;
+            variables.element(name, &key)?
+        }
+        None => variables.parameter(name),
+    };
+    if value.is_none() && variables.is_nounset() {
+        let name = match &reference.subscript {
+            Some(subscript) => format!("{}[{}]", name, subscript),
+            None => name.clone(),
+        };
+        return Err(Unbound(name).into());
+    }
+    if reference.length {
+        let length = value.map_or(0, |value| value.chars().count());
+        return Ok(Expanded::Value(Some(length.to_string())));
+    }
+    let value = match (&reference.slice, value) {
+        (Some((offset, length)), Some(value)) => {
+            let chars: Vec<char> = value.chars().collect();
+            let (start, end) = slice_range(chars.len(), offset, length, variables)?;
+            Some(chars[start..end].iter().collect())
+        }
+        (_, value) => value,
+    };
+    Ok(Expanded::Value(value))
+}
+
+/// Range of a `:offset:length` slice of `len` items. Negative offsets count back from the end,
+/// as do negative lengths for strings.
+fn slice_range(
+    len: usize,
+    offset: &str,
+    length: &Option<String>,
+    variables: &mut Variables,
+) -> Result<(usize, usize)> {
+    let len = len as i64;
+    let offset = expand_arithmetic(offset, variables)?;
+    let start = if offset < 0 { len + offset } else { offset };
+    if !(0..=len).contains(&start) {
+        return Ok((0, 0));
+    }
+    let end = match length {
+        Some(length) => {
+            let length = expand_arithmetic(length, variables)?;
+            let end = if length < 0 {
+                len + length
+            } else {
+                start + length
+            };
+            anyhow::ensure!(end >= start, "{}: substring expression < 0", length);
+            end.min(len)
+        }
+        None => len,
+    };
+    Ok((start as usize, end as usize))
+}
+
+/// Parses the parameter reference starting with the `$` at `start`, returning its name and the
+/// index just past it. Returns `None` when the `$` is to be taken literally.
+fn parameter_at(chars: &[(char, Quoting)], start: usize) -> Option<(String, usize)> {
+    let quoting = chars.get(start)?.1;
+    let char_at = |i: usize| chars.get(i).filter(|(_, q)| *q == quoting).map(|(c, _)| *c);
+    let mut i = start + 1;
+    let mut name = String::new();
+    match char_at(i)? {
+        '{' => {
+            i += 1;
+            while let Some(c) = char_at(i)
+                && c != '}'
+            {
+                name.push(c);
+                i += 1;
+            }
+            char_at(i)?;
+            i += 1;
+        }
+        c if c.is_ascii_alphabetic() || c == '_' => {
+            while let Some(c) = char_at(i)
+                && (c.is_ascii_alphanumeric() || c == '_')
+            {
+                name.push(c);
+                i += 1;
+            }
+        }
+        c if c.is_ascii_digit() || "$!#?-@*".contains(c) => {
+            name.push(c);
+            i += 1;
+        }
+        _ => return None,
+    }
+    Some((name, i))
+}
+
+/// Parses the `$((...))` starting at `start`, returning the expression and the index just past it.
+fn arithmetic_at(chars: &[(char, Quoting)], start: usize) -> Option<(String, usize)> {
+    let quoting = chars.get(start)?.1;
+    let char_at = |i: usize| chars.get(i).filter(|(_, q)| *q == quoting).map(|(c, _)| *c);
+    if char_at(start + 1)? != '(' || char_at(start + 2)? != '(' {
+        return None;
+    }
+    let mut depth = 0;
+    let mut i = start + 3;
+    while let Some(c) = char_at(i) {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' if char_at(i + 1) == Some(')') => {
+                let expression = chars[start + 3..i].iter().map(|(c, _)| c).collect();
+                return Some((expression, i + 2));
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Splits a field on `IFS` characters produced by unquoted expansions. Runs of IFS whitespace
+/// separate fields and are trimmed at both ends, while every other IFS character delimits a
+/// field on its own, so adjacent ones produce empty fields.
+fn split_field(field: Word, ifs: &str) -> Vec<Word> {
+    let mut fields = Vec::new();
+    let mut current = Word::default();
+    let mut after_whitespace = false; // the previous field ended at IFS whitespace
+    for (c, quoting) in field.chars {
+        if quoting == Quoting::Expanded && ifs.contains(c) {
+            if matches!(c, ' ' | '\t' | '\n') {
+                if !current.is_empty() {
+                    fields.push(std::mem::take(&mut current));
+                    after_whitespace = true;
+                }
+            } else if after_whitespace && current.is_empty() {
+                after_whitespace = false;
+            } else {
+                fields.push(std::mem::take(&mut current));
+            }
+        } else {
+            if quoting == Quoting::Double || quoting == Quoting::Literal {
+                current.quoted = true;
+            }
+            current.push(c, quoting);
+            after_whitespace = false;
+        }
+    }
+    if !current.is_empty() {
+        fields.push(current);
+    } else if fields.is_empty() && field.quoted {
+        fields.push(Word {
+            chars: Vec::new(),
+            quoted: true,
+        });
+    }
+    fields
+}
+
+/// Replaces a field containing unquoted `*`, `?` or `[...]` with the sorted list of matching
+/// paths, ignoring case with `nocaseglob`. Without matches the field is kept as is, unless
+/// `nullglob` or `failglob` is set.
+fn pathname_expansion(field: &Word, options: &Options) -> Result<Vec<String>> {
+    let pattern = pattern_from_word(field);
+    let extglob = options.shopt("extglob");
+    if options.option("noglob") || !has_wildcards(&pattern, extglob) {
+        return Ok(vec![field.text()]);
+    }
+    let components: Vec<&[PatternChar]> = pattern.split(|(c, _)| *c == '/').collect();
+    let mut paths = vec![String::new()];
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i + 1 == components.len();
+        if component.is_empty() {
+            // Leading, doubled or trailing slash.
+            paths = if i == 0 {
+                vec![String::from("/")]
+            } else {
+                paths
+                    .into_iter()
+                    .filter(|path| Path::new(path).is_dir())
+                    .map(|path| join_path(&path, ""))
+                    .collect()
+            };
+            continue;
+        }
+        let mut next = Vec::new();
+        for path in paths {
+            if !has_wildcards(component, extglob) {
+                let name: String = component.iter().map(|(c, _)| c).collect();
+                next.push(join_path(&path, &name));
+            } else if *component == [('*', false), ('*', false)] && options.shopt("globstar") {
+                if !is_last {
+                    next.push(path.clone());
+                }
+                walk_directory(&path, !is_last, options, &mut next);
+            } else {
+                let nocase = options.shopt("nocaseglob");
+                let lowercase_component = lowercase(component);
+                for name in read_directory(&path) {
+                    let matches = match nocase {
+                        true => {
+                            pattern_matches(&lowercase_component, &name.to_lowercase(), extglob)
+                        }
+                        false => pattern_matches(component, &name, extglob),
+                    };
+                    if (!name.starts_with('.') || component[0].0 == '.' || options.shopt("dotglob"))
+                        && matches
+                    {
+                        next.push(join_path(&path, &name));
+                    }
+                }
+            }
+        }
+        paths = next
+            .into_iter()
+            .filter(|path| {
+                let path = if path.is_empty() { "." } else { path };
+                fs::symlink_metadata(path).is_ok() && (is_last || Path::new(path).is_dir())
+            })
+            .collect();
+    }
+    paths.sort();
+    paths.dedup();
+    if paths.is_empty() {
+        if options.shopt("failglob") {
+            anyhow::bail!("no match: {}", field.text())
+        } else if !options.shopt("nullglob") {
+            paths.push(field.text());
+        }
+    }
+    Ok(paths)
+}
+
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+fn read_directory(dir: &str) -> Vec<String> {
+    let dir = if dir.is_empty() { "." } else { dir };
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().display().to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Collects everything below `dir` for a `**` component, or only directories when `dirs_only`.
+fn walk_directory(dir: &str, dirs_only: bool, options: &Options, paths: &mut Vec<String>) {
+    for name in read_directory(dir) {
+        if name.starts_with('.') && !options.shopt("dotglob") {
+            continue;
+        }
+        let path = join_path(dir, &name);
+        let is_dir = fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_dir());
+        if is_dir || !dirs_only {
+            paths.push(path.clone());
+        }
+        if is_dir {
+            walk_directory(&path, dirs_only, options, paths);
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use std::{collections::HashSet, fmt::Display, process::Child};
+use std::{collections::HashSet, fmt::Display};
+
+use crate::command::{send_signal, try_wait_pid};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum JobStatus {
@@ -19,7 +21,7 @@ impl Display for JobStatus {
 
 #[derive(Debug)]
 pub struct Job {
-    pub child: Child,
+    pub pid: u32,
     pub number: u32, // number in the job queue
     pub command: String,
     pub status: JobStatus,
@@ -71,7 +73,10 @@ impl Jobs {
 
     pub fn update_status(&mut self) {
         for job in self.jobs.iter_mut() {
-            match job.child.try_wait() {
+            if job.status != JobStatus::Running {
+                continue;
+            }
+            match try_wait_pid(job.pid) {
                 Ok(status) => {
                     if status.is_some() {
                         job.status = JobStatus::Done;
@@ -94,6 +99,20 @@ impl Jobs {
         self.jobs.retain(|job| job.status == JobStatus::Running);
     }
 
+    pub fn has_running(&self) -> bool {
+        self.jobs.iter().any(|job| job.status == JobStatus::Running)
+    }
+
+    /// Sends `SIGHUP` to the running jobs, as an exiting shell does with `huponexit`.
+    pub fn hang_up(&self) {
+        for job in &self.jobs {
+            if job.status == JobStatus::Running {
+                // The job may have exited since its status was updated.
+                let _ = send_signal(job.pid, libc::SIGHUP);
+            }
+        }
+    }
+
     pub fn value(&self) -> &[Job] {
         &self.jobs
     }
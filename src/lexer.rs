@@ -0,0 +1,471 @@
+use std::fmt::Display;
+
+/// How a character of a word was quoted in the input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quoting {
+    /// Plain unquoted text.
+    Unquoted,
+    /// Inside double quotes: parameters expand, but the result is not split.
+    Double,
+    /// Single-quoted or backslash-escaped: taken literally.
+    Literal,
+    /// Result of an unquoted expansion: subject to field splitting.
+    Expanded,
+}
+
+/// A word of input that remembers how each of its characters was quoted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Word {
+    pub chars: Vec<(char, Quoting)>,
+    pub quoted: bool, // contains quotes, so it survives expansion even when empty
+}
+
+impl Word {
+    pub fn push(&mut self, c: char, quoting: Quoting) {
+        self.chars.push((c, quoting));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty() && !self.quoted
+    }
+
+    /// The word with quotes removed.
+    pub fn text(&self) -> String {
+        self.chars.iter().map(|(c, _)| c).collect()
+    }
+
+    /// Splits an assignment word `NAME=value`, `NAME+=value` or `NAME[subscript]=value`.
+    pub fn assignment(&self) -> Option<Assignment> {
+        let unquoted = |i: usize| {
+            self.chars
+                .get(i)
+                .filter(|(_, q)| *q == Quoting::Unquoted)
+                .map(|(c, _)| *c)
+        };
+        let mut i = 0;
+        while unquoted(i).is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+            i += 1;
+        }
+        let name: String = self.chars[..i].iter().map(|(c, _)| c).collect();
+        if !is_name(&name) {
+            return None;
+        }
+        let mut subscript = None;
+        if unquoted(i) == Some('[') {
+            let end = closing_bracket(&self.chars, i)?;
+            subscript = Some(Word {
+                chars: self.chars[i + 1..end].to_vec(),
+                quoted: self.chars[i + 1..end]
+                    .iter()
+                    .any(|(_, q)| *q != Quoting::Unquoted),
+            });
+            i = end + 1;
+        }
+        let append = unquoted(i) == Some('+');
+        if append {
+            i += 1;
+        }
+        if unquoted(i) != Some('=') {
+            return None;
+        }
+        let value = Word {
+            chars: self.chars[i + 1..].to_vec(),
+            quoted: self.quoted,
+        };
+        Some(Assignment {
+            name,
+            subscript,
+            append,
+            value,
+        })
+    }
+
+    /// Splits an element `[key]=value` of a compound array assignment.
+    pub fn keyed_element(&self) -> Option<(Word, Word)> {
+        if self.chars.first() != Some(&('[', Quoting::Unquoted)) {
+            return None;
+        }
+        let end = closing_bracket(&self.chars, 0)?;
+        if self.chars.get(end + 1) != Some(&('=', Quoting::Unquoted)) {
+            return None;
+        }
+        let key = Word {
+            chars: self.chars[1..end].to_vec(),
+            quoted: true,
+        };
+        let value = Word {
+            chars: self.chars[end + 2..].to_vec(),
+            quoted: self.quoted,
+        };
+        Some((key, value))
+    }
+
+    /// The word's text when it is entirely unquoted, used to recognize reserved words.
+    pub fn unquoted_text(&self) -> Option<String> {
+        (!self.quoted).then(|| self.text())
+    }
+}
+
+impl Display for Word {
+    /// Writes the word back as shell input, quoting what was quoted.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.chars.is_empty() && self.quoted {
+            return write!(f, "''");
+        }
+        let mut i = 0;
+        while i < self.chars.len() {
+            let quoting = self.chars[i].1;
+            let len = self.chars[i..]
+                .iter()
+                .position(|(_, q)| *q != quoting)
+                .unwrap_or(self.chars.len() - i);
+            let run: String = self.chars[i..i + len].iter().map(|(c, _)| c).collect();
+            match quoting {
+                Quoting::Unquoted | Quoting::Expanded => write!(f, "{run}")?,
+                Quoting::Double => {
+                    write!(f, "\"{}\"", run.replace('\\', "\\\\").replace('"', "\\\""))?
+                }
+                Quoting::Literal => write!(f, "'{}'", run.replace('\'', "'\\''"))?,
+            }
+            i += len;
+        }
+        Ok(())
+    }
+}
+
+/// An assignment word such as `NAME=value`, `NAME+=value` or `NAME[subscript]=value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub subscript: Option<Word>,
+    pub append: bool, // `+=`
+    pub value: Word,
+}
+
+impl Assignment {
+    /// Source text of the elements of a compound array value `(a b [k]=v)`.
+    pub fn array_elements(&self) -> Option<String> {
+        let chars = &self.value.chars;
+        if chars.first() != Some(&('(', Quoting::Unquoted))
+            || chars.last() != Some(&(')', Quoting::Unquoted))
+            || chars.len() < 2
+        {
+            return None;
+        }
+        Some(chars[1..chars.len() - 1].iter().map(|(c, _)| c).collect())
+    }
+}
+
+/// Index of the unquoted `]` closing the unquoted `[` at `start`.
+pub fn closing_bracket(chars: &[(char, Quoting)], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, &(c, quoting)) in chars.iter().enumerate().skip(start) {
+        match (c, quoting) {
+            ('[', Quoting::Unquoted) => depth += 1,
+            (']', Quoting::Unquoted) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether `name` is a valid variable name: a letter or underscore followed by alphanumerics.
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    Operator(&'static str),
+    IoNumber(i32),      // the `2` of `2>file`
+    Arithmetic(String), // `(( expression ))`
+    Newline,
+}
+
+/// Input that ends in the middle of a construct; reading more lines may complete it.
+#[derive(Debug)]
+pub struct Incomplete(pub String);
+
+impl Display for Incomplete {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "syntax error: unexpected end of file{}", self.0)
+    }
+}
+
+impl std::error::Error for Incomplete {}
+
+// Longest operators first, so that prefixes match last.
+const OPERATORS: [&str; 18] = [
+    ";;&", "&>>", ";;", ";&", "&&", "&>", "||", ">>", ">|", ">&", "<&", "&", "|", ";", "(", ")",
+    "<", ">",
+];
+
+/// Splits the input into tokens. With `extglob`, words may contain the pattern groups `?(...)`,
+/// `*(...)`, `+(...)`, `@(...)` and `!(...)`.
+pub fn tokenize(input: &str, extglob: bool) -> anyhow::Result<Vec<Token>> {
+    let tokens = tokenize_with_offsets(input, extglob)?;
+    Ok(tokens.into_iter().map(|(token, _)| token).collect())
+}
+
+/// Splits the input into tokens, each with the index of the character it starts at.
+pub fn tokenize_with_offsets(input: &str, extglob: bool) -> anyhow::Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut offsets = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        match chars[i] {
+            ' ' | '\t' => i += 1,
+            '\n' => {
+                tokens.push(Token::Newline);
+                i += 1;
+            }
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '\\' if chars.get(i + 1) == Some(&'\n') => i += 2,
+            _ if follows_regex_operator(&tokens) => {
+                tokens.push(Token::Word(read_word(&chars, &mut i, true, extglob)?));
+            }
+            '(' if chars.get(i + 1) == Some(&'(') => {
+                let end = arithmetic_end(&chars, i)?;
+                tokens.push(Token::Arithmetic(chars[i + 2..end - 2].iter().collect()));
+                i = end;
+            }
+            _ => {
+                if let Some(operator) = OPERATORS.iter().find(|op| {
+                    op.chars()
+                        .enumerate()
+                        .all(|(j, c)| chars.get(i + j) == Some(&c))
+                }) {
+                    tokens.push(Token::Operator(operator));
+                    i += operator.len();
+                } else {
+                    let word = read_word(&chars, &mut i, false, extglob)?;
+                    if !word.quoted
+                        && word.chars.iter().all(|(c, _)| c.is_ascii_digit())
+                        && matches!(chars.get(i), Some('<' | '>'))
+                        && let Ok(fd) = word.text().parse()
+                    {
+                        tokens.push(Token::IoNumber(fd));
+                    } else {
+                        tokens.push(Token::Word(word));
+                    }
+                }
+            }
+        }
+        offsets.resize(tokens.len(), start);
+    }
+    Ok(tokens.into_iter().zip(offsets).collect())
+}
+
+/// Whether the next word is the regular expression after `=~`, which may contain `(`, `)`, `|`,
+/// `<` and `>`.
+fn follows_regex_operator(tokens: &[Token]) -> bool {
+    matches!(tokens.last(), Some(Token::Word(word)) if word.unquoted_text().as_deref() == Some("=~"))
+}
+
+fn read_word(chars: &[char], i: &mut usize, is_regex: bool, extglob: bool) -> anyhow::Result<Word> {
+    let mut word = Word::default();
+    let mut depth = 0; // of the `extglob` groups the word is in
+    while let Some(&c) = chars.get(*i) {
+        match c {
+            ' ' | '\t' | '\n' | '&' | ';' => break,
+            '?' | '*' | '+' | '@' | '!' if extglob && chars.get(*i + 1) == Some(&'(') => {
+                word.push(c, Quoting::Unquoted);
+                word.push('(', Quoting::Unquoted);
+                depth += 1;
+                *i += 2;
+            }
+            '(' | '|' | ')' if depth > 0 => {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                word.push(c, Quoting::Unquoted);
+                *i += 1;
+            }
+            '(' if !is_regex && word.assignment().is_some_and(|a| a.value.chars.is_empty()) => {
+                read_array(chars, i, &mut word)?;
+            }
+            '|' | '(' | ')' | '<' | '>' if !is_regex => break,
+            '\\' => {
+                *i += 1;
+                match chars.get(*i) {
+                    Some('\n') => {}
+                    Some(&c) => {
+                        word.quoted = true;
+                        word.push(c, Quoting::Literal);
+                    }
+                    None => return Err(Incomplete(String::new()).into()),
+                }
+                *i += 1;
+            }
+            '\'' => {
+                word.quoted = true;
+                *i += 1;
+                loop {
+                    match chars.get(*i) {
+                        Some('\'') => break,
+                        Some(&c) => word.push(c, Quoting::Literal),
+                        None => {
+                            return Err(Incomplete(String::from(
+                                " while looking for matching `''",
+                            ))
+                            .into());
+                        }
+                    }
+                    *i += 1;
+                }
+                *i += 1;
+            }
+            '"' => {
+                word.quoted = true;
+                *i += 1;
+                loop {
+                    match chars.get(*i) {
+                        Some('"') => break,
+                        Some('\\') => {
+                            *i += 1;
+                            let c = match chars.get(*i) {
+                                Some('\n') => None, // line continuation
+                                Some('n') => Some('\n'),
+                                Some('t') => Some('\t'),
+                                Some('0') => Some('\0'),
+                                Some(&c) => Some(c),
+                                None => {
+                                    return Err(Incomplete(String::from(
+                                        " while looking for matching `\"'",
+                                    ))
+                                    .into());
+                                }
+                            };
+                            if let Some(c) = c {
+                                word.push(c, Quoting::Literal);
+                            }
+                        }
+                        Some('$') if chars.get(*i + 1) == Some(&'(') => {
+                            read_arithmetic(chars, i, &mut word, Quoting::Double)?;
+                            continue;
+                        }
+                        Some(&c) => word.push(c, Quoting::Double),
+                        None => {
+                            return Err(Incomplete(String::from(
+                                " while looking for matching `\"'",
+                            ))
+                            .into());
+                        }
+                    }
+                    *i += 1;
+                }
+                *i += 1;
+            }
+            '$' if chars.get(*i + 1) == Some(&'(') => {
+                read_arithmetic(chars, i, &mut word, Quoting::Unquoted)?;
+            }
+            '$' if chars.get(*i + 1) == Some(&'{') => read_parameter(chars, i, &mut word)?,
+            _ => {
+                word.push(c, Quoting::Unquoted);
+                *i += 1;
+            }
+        }
+    }
+    Ok(word)
+}
+
+/// Copies the source text of a compound array value `(...)` into the word, to be tokenized again
+/// when it is assigned.
+fn read_array(chars: &[char], i: &mut usize, word: &mut Word) -> anyhow::Result<()> {
+    let mut quote = None;
+    let mut depth = 0;
+    while let Some(&c) = chars.get(*i) {
+        word.push(c, Quoting::Unquoted);
+        *i += 1;
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None | Some('"'), '\\') => {
+                if let Some(&escaped) = chars.get(*i) {
+                    word.push(escaped, Quoting::Unquoted);
+                    *i += 1;
+                }
+            }
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(Incomplete(String::from(" while looking for matching `)'")).into())
+}
+
+/// Copies an unquoted `${...}` parameter into the word, spaces included.
+fn read_parameter(chars: &[char], i: &mut usize, word: &mut Word) -> anyhow::Result<()> {
+    let mut depth = 0;
+    while let Some(&c) = chars.get(*i) {
+        word.push(c, Quoting::Unquoted);
+        *i += 1;
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(Incomplete(String::from(" while looking for matching `}'")).into())
+}
+
+/// Copies a `$((...))` arithmetic expansion into the word, spaces included.
+fn read_arithmetic(
+    chars: &[char],
+    i: &mut usize,
+    word: &mut Word,
+    quoting: Quoting,
+) -> anyhow::Result<()> {
+    if chars.get(*i + 2) != Some(&'(') {
+        anyhow::bail!("syntax error: command substitution is not supported")
+    }
+    let end = arithmetic_end(chars, *i + 1)?;
+    chars[*i..end].iter().for_each(|c| word.push(*c, quoting));
+    *i = end;
+    Ok(())
+}
+
+/// Index just past the `))` closing the `((` at `start`.
+fn arithmetic_end(chars: &[char], start: usize) -> anyhow::Result<usize> {
+    let mut depth = 0;
+    let mut i = start + 2;
+    while i < chars.len() {
+        match chars[i] {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ')' if chars.get(i + 1) == Some(&')') => return Ok(i + 2),
+            ')' => anyhow::bail!("syntax error near unexpected token `)'"),
+            _ => {}
+        }
+        i += 1;
+    }
+    Err(Incomplete(String::from(" while looking for matching `))'")).into())
+}
@@ -1,22 +1,42 @@
+mod arithmetic;
+mod ast;
 mod builtin;
 mod command;
+mod condition;
+mod expansion;
 mod job;
+mod lexer;
+mod options;
 mod parser;
+mod pattern;
+mod printf;
 mod readline;
 mod shell;
+mod signal;
+mod variable;
 
 use crate::{
     job::Jobs,
+    options::Options,
     readline::{helper::Helper, history::History},
     shell::Shell,
+    variable::Variables,
 };
 use rustyline::{Config, Editor, config::Configurer};
-use std::{collections::HashMap, env};
+use std::{collections::HashMap, env, process};
 
 fn main() -> anyhow::Result<()> {
+    let mut args = env::args();
+    let shell_name = args.next().unwrap_or_else(|| String::from("xshell"));
+    let script = args.next();
+    let positional = args.collect();
+
     let mut history = History::default();
-    if let Ok(histfile) = env::var("HISTFILE") {
+    if script.is_none()
+        && let Ok(histfile) = env::var("HISTFILE")
+    {
         history.append_from_file(&histfile)?;
+        history.start_session();
         history.set_histfile(histfile);
     };
 
@@ -26,25 +46,42 @@ fn main() -> anyhow::Result<()> {
         .build();
 
     let jobs = Jobs::new();
+    let mut options = Options::new();
+    // Like bash, only interactive shells expand aliases unless a script enables it.
+    options.set_shopt("expand_aliases", script.is_none())?;
     let completers = HashMap::new();
-    let variables = HashMap::new();
+    let variables = match &script {
+        Some(path) => Variables::new(path.clone(), positional, false),
+        None => Variables::new(shell_name, positional, true),
+    };
 
     let mut editor = Editor::<Helper, History>::with_history(config, history)?;
     let helper = Helper::new(completers);
     editor.set_helper(Some(helper));
     editor.set_auto_add_history(true);
 
-    let mut shell = Shell::new(&mut editor, jobs, variables);
-    match shell.run() {
-        Ok(_) => {}
-        Err(e) => eprintln!("Error: {e}"),
-    }
+    let mut shell = Shell::new(&mut editor, jobs, variables, options);
+    let result = match &script {
+        Some(path) => shell.run_script(path),
+        None => shell.run(),
+    };
+    let code = match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            1
+        }
+    };
+    let histappend = shell.options().shopt("histappend");
     drop(shell);
 
     let history = editor.history();
     if let Some(path) = history.histfile() {
-        history.write_to_file(path)?;
+        match histappend {
+            true => history.append_session_to_file(path)?,
+            false => history.write_to_file(path)?,
+        }
     }
 
-    Ok(())
+    process::exit(code)
 }
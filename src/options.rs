@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+const SHOPT_NAMES: [&str; 14] = [
+    "autocd",
+    "cdspell",
+    "checkjobs",
+    "cmdspell",
+    "dotglob",
+    "expand_aliases",
+    "extglob",
+    "failglob",
+    "globstar",
+    "histappend",
+    "huponexit",
+    "nocaseglob",
+    "nullglob",
+    "xpg_echo",
+];
+
+/// Options of `set -o NAME`, with the flag letter of those that have one.
+const SET_OPTIONS: [(&str, Option<char>); 7] = [
+    ("errexit", Some('e')),
+    ("noclobber", Some('C')),
+    ("noglob", Some('f')),
+    ("nounset", Some('u')),
+    ("pipefail", None),
+    ("verbose", Some('v')),
+    ("xtrace", Some('x')),
+];
+
+/// Optional shell behaviors, toggled with `shopt` and `set`.
+pub struct Options {
+    shopt: BTreeMap<&'static str, bool>,
+    set: BTreeMap<&'static str, bool>,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Options {
+            shopt: SHOPT_NAMES.iter().map(|name| (*name, false)).collect(),
+            set: SET_OPTIONS.iter().map(|(name, _)| (*name, false)).collect(),
+        }
+    }
+
+    pub fn option(&self, name: &str) -> bool {
+        self.set.get(name).copied().unwrap_or(false)
+    }
+
+    pub fn set_option(&mut self, name: &str, value: bool) -> anyhow::Result<()> {
+        match self.set.get_mut(name) {
+            Some(option) => *option = value,
+            None => anyhow::bail!("set: {}: invalid option name", name),
+        }
+        Ok(())
+    }
+
+    /// Sets the option of a flag letter, as with `set -e`.
+    pub fn set_flag(&mut self, flag: char, value: bool) -> anyhow::Result<()> {
+        match SET_OPTIONS.iter().find(|(_, letter)| *letter == Some(flag)) {
+            Some((name, _)) => self.set_option(name, value),
+            None => anyhow::bail!("set: -{}: invalid option", flag),
+        }
+    }
+
+    pub fn options(&self) -> impl Iterator<Item = (&'static str, bool)> {
+        self.set.iter().map(|(name, value)| (*name, *value))
+    }
+
+    /// Letters of the flags that are on.
+    pub fn flags(&self) -> String {
+        SET_OPTIONS
+            .iter()
+            .filter(|(name, _)| self.option(name))
+            .filter_map(|(_, letter)| *letter)
+            .collect()
+    }
+
+    pub fn shopt(&self, name: &str) -> bool {
+        self.shopt.get(name).copied().unwrap_or(false)
+    }
+
+    pub fn set_shopt(&mut self, name: &str, value: bool) -> anyhow::Result<()> {
+        match self.shopt.get_mut(name) {
+            Some(option) => *option = value,
+            None => anyhow::bail!("shopt: {}: invalid shell option name", name),
+        }
+        Ok(())
+    }
+
+    pub fn shopts(&self) -> impl Iterator<Item = (&'static str, bool)> {
+        self.shopt.iter().map(|(name, value)| (*name, *value))
+    }
+}
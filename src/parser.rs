@@ -1,259 +1,616 @@
-use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashSet},
+    rc::Rc,
+};
 
-use crate::command::ShellCommand;
+use crate::{
+    ast::{
+        AndOr, CaseItem, CaseTerminator, Command, CompoundCommand, Conditional, Connector, List,
+        ListItem, Pipeline, Redirect, RedirectKind, SimpleCommand,
+    },
+    condition::{is_binary_operator, is_unary_operator},
+    lexer::{Incomplete, Token, Word, is_name, tokenize, tokenize_with_offsets},
+};
 
-use std::{collections::HashMap, fs::OpenOptions};
+// Reserved words that end a list inside a compound command.
+const LIST_TERMINATORS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
 
-enum RedirectingState {
-    Normal,
-    RedirectingStdout,
-    RedirectingStderr,
-    AppendingStdout,
-    AppendingStderr,
+const REDIRECT_OPERATORS: [&str; 8] = ["<", ">", ">>", ">|", ">&", "<&", "&>", "&>>"];
+
+/// Alias values by name.
+pub type Aliases = BTreeMap<String, String>;
+
+pub struct Parser {
+    source: Vec<char>,
+    tokens: Vec<Token>,
+    offsets: Vec<usize>, // index in the source where each token starts
+    extglob: bool,       // whether the tokens were read with `extglob` groups
+    pos: usize,
+    aliases: Aliases,
+    alias_next: Option<usize>, // position of a word following an alias ending in a blank
 }
 
-pub fn commands_from_input(input: String) -> anyhow::Result<Vec<ShellCommand>> {
-    let tokens = token_from_input(input)?;
-    let mut cmds = Vec::new();
-    let mut state = RedirectingState::Normal;
-    let mut args = Vec::new();
-    let mut name = String::from("");
-    let mut stdout_file = None;
-    let mut stderr_file = None;
-
-    for arg in tokens {
-        match state {
-            RedirectingState::Normal => match arg.as_str() {
-                ">" | "1>" => state = RedirectingState::RedirectingStdout,
-                "2>" => state = RedirectingState::RedirectingStderr,
-                ">>" | "1>>" => state = RedirectingState::AppendingStdout,
-                "2>>" => state = RedirectingState::AppendingStderr,
-                "|" => {
-                    if name.is_empty() {
-                        anyhow::bail!("parse error: unexpected token near `|`")
-                    }
-                    if let Some(last_arg) = args.last()
-                        && last_arg == "&"
-                    {
-                        anyhow::bail!("parse error: unexpected token near `|`")
-                    }
-                    cmds.push(ShellCommand::new(
-                        name,
-                        args,
-                        stdout_file,
-                        stderr_file,
-                        false,
-                    ));
-                    name = String::from("");
-                    args = Vec::new();
-                    stdout_file = None;
-                    stderr_file = None;
-                }
-                _ => {
-                    if name.is_empty() {
-                        name = arg;
-                    } else {
-                        args.push(arg);
-                    }
-                }
-            },
-            RedirectingState::RedirectingStdout => match arg.as_str() {
-                ">" | "1>" | "2>" | ">>" | "1>>" | "2>>" | "|" => {
-                    anyhow::bail!("parse error: unexpected token near `>`")
-                }
-                _ => {
-                    let f = OpenOptions::new()
-                        .create(true)
-                        .write(true)
-                        .truncate(true)
-                        .open(&arg)?;
-                    stdout_file = Some(f);
-                    state = RedirectingState::Normal;
-                }
-            },
-            RedirectingState::RedirectingStderr => match arg.as_str() {
-                ">" | "1>" | "2>" | ">>" | "1>>" | "2>>" | "|" => {
-                    anyhow::bail!("parse error: unexpected token near `>`")
-                }
+/// Parses all complete commands of the input.
+pub fn parse(input: &str, aliases: &Aliases, extglob: bool) -> anyhow::Result<Vec<List>> {
+    let mut parser = Parser::new(input, extglob)?;
+    let mut commands = Vec::new();
+    while let Some(list) = parser.next_command(aliases, extglob)? {
+        commands.push(list);
+    }
+    Ok(commands)
+}
+
+impl Parser {
+    pub fn new(input: &str, extglob: bool) -> anyhow::Result<Self> {
+        let (tokens, offsets) = tokenize_with_offsets(input, extglob)?.into_iter().unzip();
+        Ok(Self {
+            source: input.chars().collect(),
+            tokens,
+            offsets,
+            extglob,
+            pos: 0,
+            aliases: Aliases::new(),
+            alias_next: None,
+        })
+    }
+
+    /// Parses the next complete command, which ends at a newline. Returns `None` at end of input.
+    /// Aliases are expanded as they are defined at this point, and `extglob` groups are read if it
+    /// is set at this point.
+    pub fn next_command(
+        &mut self,
+        aliases: &Aliases,
+        extglob: bool,
+    ) -> anyhow::Result<Option<List>> {
+        self.aliases.clone_from(aliases);
+        if extglob != self.extglob {
+            self.retokenize(extglob)?;
+        }
+        self.skip_newlines();
+        if self.peek().is_none() {
+            return Ok(None);
+        }
+        let list = self.parse_list(false)?;
+        match self.peek() {
+            None => {}
+            Some(Token::Newline) => self.pos += 1,
+            Some(_) => return Err(self.unexpected()),
+        }
+        Ok(Some(list))
+    }
+
+    /// Reads the tokens from the current position again, after `extglob` was set or unset.
+    fn retokenize(&mut self, extglob: bool) -> anyhow::Result<()> {
+        let start = self
+            .offsets
+            .get(self.pos)
+            .copied()
+            .unwrap_or(self.source.len());
+        let rest: String = self.source[start..].iter().collect();
+        let (tokens, offsets): (Vec<_>, Vec<_>) = tokenize_with_offsets(&rest, extglob)?
+            .into_iter()
+            .map(|(token, offset)| (token, start + offset))
+            .unzip();
+        self.tokens.truncate(self.pos);
+        self.tokens.extend(tokens);
+        self.offsets.truncate(self.pos);
+        self.offsets.extend(offsets);
+        self.extglob = extglob;
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek_reserved(&self, reserved: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.unquoted_text().as_deref() == Some(reserved))
+    }
+
+    fn expect_reserved(&mut self, reserved: &str) -> anyhow::Result<()> {
+        if !self.peek_reserved(reserved) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn expect_operator(&mut self, operator: &'static str) -> anyhow::Result<()> {
+        if self.peek() != Some(&Token::Operator(operator)) {
+            return Err(self.unexpected());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Error for the token at the current position, or `Incomplete` at end of input.
+    fn unexpected(&self) -> anyhow::Error {
+        let token = match self.peek() {
+            None => return Incomplete(String::new()).into(),
+            Some(Token::Word(word)) => word.text(),
+            Some(Token::Operator(operator)) => operator.to_string(),
+            Some(Token::IoNumber(fd)) => fd.to_string(),
+            Some(Token::Arithmetic(_)) => String::from("(("),
+            Some(Token::Newline) => String::from("newline"),
+        };
+        anyhow::anyhow!("syntax error near unexpected token `{}'", token)
+    }
+
+    fn at_list_end(&self, multiline: bool) -> bool {
+        match self.peek() {
+            None => true,
+            Some(Token::Newline) => !multiline,
+            Some(Token::Operator(operator)) => matches!(*operator, ")" | ";;" | ";&" | ";;&"),
+            Some(Token::Word(_)) => LIST_TERMINATORS.iter().any(|word| self.peek_reserved(word)),
+            _ => false,
+        }
+    }
+
+    /// Parses and-or lists separated by `;` or `&`. Inside compound commands (`multiline`),
+    /// newlines separate them as well.
+    fn parse_list(&mut self, multiline: bool) -> anyhow::Result<List> {
+        let mut items = Vec::new();
+        loop {
+            if multiline {
+                self.skip_newlines();
+            }
+            if self.at_list_end(multiline) {
+                break;
+            }
+            let and_or = self.parse_and_or()?;
+            let is_background_job = match self.peek() {
+                Some(Token::Operator("&")) => true,
+                Some(Token::Operator(";")) => false,
+                Some(Token::Newline) if multiline => false,
                 _ => {
-                    let f = OpenOptions::new()
-                        .create(true)
-                        .write(true)
-                        .truncate(true)
-                        .open(&arg)?;
-                    stderr_file = Some(f);
-                    state = RedirectingState::Normal;
+                    items.push(ListItem {
+                        and_or,
+                        is_background_job: false,
+                    });
+                    break;
                 }
-            },
-            RedirectingState::AppendingStdout => match arg.as_str() {
-                ">" | "1>" | "2>" | ">>" | "1>>" | "2>>" | "|" => {
-                    anyhow::bail!("parse error: unexpected token near `>`")
+            };
+            self.pos += 1;
+            items.push(ListItem {
+                and_or,
+                is_background_job,
+            });
+        }
+        if items.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(List { items })
+    }
+
+    /// A possibly empty list, as the body of a `case` item.
+    fn parse_optional_list(&mut self) -> anyhow::Result<List> {
+        self.skip_newlines();
+        if self.at_list_end(true) {
+            Ok(List::default())
+        } else {
+            self.parse_list(true)
+        }
+    }
+
+    fn parse_and_or(&mut self) -> anyhow::Result<AndOr> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek() {
+                Some(Token::Operator("&&")) => Connector::And,
+                Some(Token::Operator("||")) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((connector, self.parse_pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn parse_pipeline(&mut self) -> anyhow::Result<Pipeline> {
+        let negated = self.peek_reserved("!");
+        if negated {
+            self.pos += 1;
+        }
+        let mut commands = vec![self.parse_command()?];
+        while self.peek() == Some(&Token::Operator("|")) {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.parse_command()?);
+        }
+        Ok(Pipeline { negated, commands })
+    }
+
+    /// Replaces an alias at the current position with the tokens of its value, repeatedly, but
+    /// never expanding the same alias twice. When a value ends in a blank, the word after it is
+    /// checked for an alias as well.
+    fn expand_aliases(&mut self) -> anyhow::Result<()> {
+        self.alias_next = None;
+        let mut expanded = HashSet::new();
+        while let Some(Token::Word(word)) = self.peek()
+            && let Some(name) = word.unquoted_text()
+            && !expanded.contains(&name)
+            && let Some(value) = self.aliases.get(&name)
+        {
+            let tokens = tokenize(value, self.extglob)?;
+            self.alias_next = value
+                .ends_with([' ', '\t'])
+                .then_some(self.pos + tokens.len());
+            // The tokens of the value start where the alias did.
+            let offsets = vec![self.offsets[self.pos]; tokens.len()];
+            self.offsets.splice(self.pos..self.pos + 1, offsets);
+            self.tokens.splice(self.pos..self.pos + 1, tokens);
+            expanded.insert(name);
+        }
+        Ok(())
+    }
+
+    fn parse_command(&mut self) -> anyhow::Result<Command> {
+        self.expand_aliases()?;
+        let compound = match self.peek() {
+            Some(Token::Arithmetic(expression)) => {
+                let expression = expression.clone();
+                self.pos += 1;
+                CompoundCommand::Arithmetic(expression)
+            }
+            Some(Token::Operator("(")) => self.parse_subshell()?,
+            Some(Token::Word(word)) => match word.unquoted_text().as_deref() {
+                Some("{") => self.parse_group()?,
+                Some("[[") => self.parse_conditional_command()?,
+                Some("function") => return self.parse_function(true),
+                Some(name)
+                    if is_name(name)
+                        && self.tokens.get(self.pos + 1) == Some(&Token::Operator("(")) =>
+                {
+                    return self.parse_function(false);
                 }
-                _ => {
-                    let f = OpenOptions::new().create(true).append(true).open(&arg)?;
-                    stdout_file = Some(f);
-                    state = RedirectingState::Normal;
-                }
-            },
-            RedirectingState::AppendingStderr => match arg.as_str() {
-                ">" | "1>" | "2>" | ">>" | "1>>" | "2>>" | "|" => {
-                    anyhow::bail!("parse error: unexpected token near `>`")
-                }
-                _ => {
-                    let f = OpenOptions::new().create(true).append(true).open(&arg)?;
-                    stderr_file = Some(f);
-                    state = RedirectingState::Normal;
+                Some("if") => self.parse_if()?,
+                Some("while") => self.parse_while(false)?,
+                Some("until") => self.parse_while(true)?,
+                Some("for") => self.parse_for()?,
+                Some("case") => self.parse_case()?,
+                Some(reserved) if LIST_TERMINATORS.contains(&reserved) => {
+                    return Err(self.unexpected());
                 }
+                _ => return self.parse_simple_command().map(Command::Simple),
             },
+            _ => return self.parse_simple_command().map(Command::Simple),
+        };
+        let mut redirects = Vec::new();
+        while self.at_redirect() {
+            redirects.push(self.parse_redirect()?);
         }
+        Ok(Command::Compound(compound, redirects))
     }
 
-    if name.is_empty() {
-        anyhow::bail!("parse error")
-    } else {
-        let is_background_job = args.pop_if(|x| x == "&").is_some();
-        cmds.push(ShellCommand::new(
-            name,
-            args,
-            stdout_file,
-            stderr_file,
-            is_background_job,
-        ));
+    fn parse_simple_command(&mut self) -> anyhow::Result<SimpleCommand> {
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+        loop {
+            if self.alias_next == Some(self.pos) {
+                self.expand_aliases()?;
+            }
+            if self.at_redirect() {
+                redirects.push(self.parse_redirect()?);
+            } else if let Some(Token::Word(word)) = self.peek() {
+                words.push(word.clone());
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if words.is_empty() && redirects.is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(SimpleCommand { words, redirects })
     }
 
-    Ok(cmds)
-}
+    fn at_redirect(&self) -> bool {
+        match self.peek() {
+            Some(Token::IoNumber(_)) => true,
+            Some(Token::Operator(operator)) => REDIRECT_OPERATORS.contains(operator),
+            _ => false,
+        }
+    }
 
-enum TokenState {
-    Normal,
-    NormalEscape,
-    SingleQuote,
-    DoubleQuote,
-    DoubleQuoteEscape,
-}
+    fn parse_redirect(&mut self) -> anyhow::Result<Redirect> {
+        let mut fd = None;
+        if let Some(Token::IoNumber(number)) = self.peek() {
+            fd = Some(*number);
+            self.pos += 1;
+        }
+        let (default_fd, kind) = match self.peek() {
+            Some(Token::Operator("<")) => (0, RedirectKind::Input),
+            Some(Token::Operator(">")) => (1, RedirectKind::Output),
+            Some(Token::Operator(">>")) => (1, RedirectKind::Append),
+            Some(Token::Operator(">|")) => (1, RedirectKind::Clobber),
+            Some(Token::Operator(">&")) => (1, RedirectKind::Duplicate),
+            Some(Token::Operator("<&")) => (0, RedirectKind::Duplicate),
+            Some(Token::Operator("&>")) => (1, RedirectKind::OutputAll),
+            Some(Token::Operator("&>>")) => (1, RedirectKind::AppendAll),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        let Some(Token::Word(target)) = self.peek() else {
+            return Err(self.unexpected());
+        };
+        let target = target.clone();
+        self.pos += 1;
+        Ok(Redirect {
+            fd: fd.unwrap_or(default_fd),
+            kind,
+            target,
+        })
+    }
 
-fn token_from_input(input: String) -> anyhow::Result<Vec<String>> {
-    let mut args = Vec::new();
-    let mut state = TokenState::Normal;
-    let mut token = String::new();
-    for c in input.trim().chars() {
-        match state {
-            TokenState::Normal => match c {
-                ' ' => {
-                    if !token.is_empty() {
-                        args.push(token);
-                        token = String::new();
-                    }
-                }
-                '\\' => state = TokenState::NormalEscape,
-                '\'' => state = TokenState::SingleQuote,
-                '\"' => state = TokenState::DoubleQuote,
-                _ => token.push(c),
-            },
-            TokenState::NormalEscape => {
-                token.push(c);
-                state = TokenState::Normal;
+    fn parse_group(&mut self) -> anyhow::Result<CompoundCommand> {
+        self.expect_reserved("{")?;
+        let body = self.parse_list(true)?;
+        self.expect_reserved("}")?;
+        Ok(CompoundCommand::Group(body))
+    }
+
+    fn parse_conditional_command(&mut self) -> anyhow::Result<CompoundCommand> {
+        self.expect_reserved("[[")?;
+        let expression = self.parse_conditional_or()?;
+        self.expect_reserved("]]")?;
+        Ok(CompoundCommand::Conditional(expression))
+    }
+
+    fn parse_conditional_or(&mut self) -> anyhow::Result<Conditional> {
+        let mut expression = self.parse_conditional_and()?;
+        while self.peek() == Some(&Token::Operator("||")) {
+            self.pos += 1;
+            let rhs = self.parse_conditional_and()?;
+            expression = Conditional::Or(Box::new(expression), Box::new(rhs));
+        }
+        Ok(expression)
+    }
+
+    fn parse_conditional_and(&mut self) -> anyhow::Result<Conditional> {
+        let mut expression = self.parse_conditional_not()?;
+        while self.peek() == Some(&Token::Operator("&&")) {
+            self.pos += 1;
+            let rhs = self.parse_conditional_not()?;
+            expression = Conditional::And(Box::new(expression), Box::new(rhs));
+        }
+        Ok(expression)
+    }
+
+    fn parse_conditional_not(&mut self) -> anyhow::Result<Conditional> {
+        self.skip_newlines();
+        if self.peek_reserved("!") {
+            self.pos += 1;
+            return Ok(Conditional::Not(Box::new(self.parse_conditional_not()?)));
+        }
+        if self.peek() == Some(&Token::Operator("(")) {
+            self.pos += 1;
+            let expression = self.parse_conditional_or()?;
+            self.skip_newlines();
+            self.expect_operator(")")?;
+            return Ok(expression);
+        }
+        let lhs = self.conditional_word()?;
+        let op = match self.peek() {
+            Some(Token::Operator(op @ ("<" | ">"))) => Some(op.to_string()),
+            Some(Token::Word(word)) => word
+                .unquoted_text()
+                .filter(|op| op == "=~" || is_binary_operator(op)),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.pos += 1;
+            let rhs = self.conditional_word()?;
+            return Ok(Conditional::Binary(lhs, op, rhs));
+        }
+        match lhs.unquoted_text() {
+            Some(op) if is_unary_operator(&op) && !self.peek_reserved("]]") => {
+                let operand = self.conditional_word()?;
+                Ok(Conditional::Unary(op, operand))
             }
-            TokenState::SingleQuote => match c {
-                '\'' => state = TokenState::Normal,
-                _ => token.push(c),
-            },
-            TokenState::DoubleQuote => match c {
-                '\"' => state = TokenState::Normal,
-                '\\' => state = TokenState::DoubleQuoteEscape,
-                _ => token.push(c),
-            },
-            TokenState::DoubleQuoteEscape => {
-                match c {
-                    '\\' => token.push('\\'),
-                    '\'' => token.push('\''),
-                    '\"' => token.push('\"'),
-                    'n' => token.push('\n'),
-                    't' => token.push('\t'),
-                    '0' => token.push('\0'),
-                    _ => token.push(c),
-                };
-                state = TokenState::DoubleQuote;
+            _ => Ok(Conditional::Word(lhs)),
+        }
+    }
+
+    fn conditional_word(&mut self) -> anyhow::Result<Word> {
+        match self.peek() {
+            Some(Token::Word(word)) if !self.peek_reserved("]]") => {
+                let word = word.clone();
+                self.pos += 1;
+                Ok(word)
             }
+            _ => Err(self.unexpected()),
         }
     }
-    if !token.is_empty() {
-        args.push(token);
+
+    fn parse_subshell(&mut self) -> anyhow::Result<CompoundCommand> {
+        self.expect_operator("(")?;
+        let body = self.parse_list(true)?;
+        self.expect_operator(")")?;
+        Ok(CompoundCommand::Subshell(body))
     }
-    match state {
-        TokenState::Normal => Ok(args),
-        TokenState::SingleQuote => anyhow::bail!("parse error: unexpected token near `'`"),
-        TokenState::DoubleQuote => anyhow::bail!("parse error: unexpected token near `\"`"),
-        TokenState::NormalEscape => anyhow::bail!("parse error: unexpected token near `\\`"),
-        TokenState::DoubleQuoteEscape => anyhow::bail!("parse error: unexpected token near `\\`"),
+
+    /// Parses `name() body` or, after the `function` keyword, `function name [()] body`.
+    fn parse_function(&mut self, keyword: bool) -> anyhow::Result<Command> {
+        if keyword {
+            self.pos += 1;
+        }
+        let name = match self.peek() {
+            Some(Token::Word(word)) if word.unquoted_text().is_some() => word.text(),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        if !keyword || self.peek() == Some(&Token::Operator("(")) {
+            self.expect_operator("(")?;
+            self.expect_operator(")")?;
+        }
+        self.skip_newlines();
+        let start = self.pos;
+        let body = self.parse_command()?;
+        if !matches!(body, Command::Compound(..)) {
+            self.pos = start;
+            return Err(self.unexpected());
+        }
+        Ok(Command::FunctionDefinition {
+            name,
+            body: Rc::new(body),
+        })
     }
-}
 
-#[derive(PartialEq)]
-enum VariableState {
-    Normal,
-    Variable,
-    VariableBrace,
-}
+    fn parse_if(&mut self) -> anyhow::Result<CompoundCommand> {
+        self.expect_reserved("if")?;
+        let mut branches = Vec::new();
+        let mut else_body = None;
+        loop {
+            let condition = self.parse_list(true)?;
+            self.expect_reserved("then")?;
+            let body = self.parse_list(true)?;
+            branches.push((condition, body));
+            if self.peek_reserved("elif") {
+                self.pos += 1;
+                continue;
+            }
+            if self.peek_reserved("else") {
+                self.pos += 1;
+                else_body = Some(self.parse_list(true)?);
+            }
+            self.expect_reserved("fi")?;
+            break;
+        }
+        Ok(CompoundCommand::If {
+            branches,
+            else_body,
+        })
+    }
 
-pub fn args_expansion(
-    args: Vec<String>,
-    variables: &HashMap<String, String>,
-) -> Result<Vec<String>> {
-    let mut res = Vec::new();
-    for arg in args {
-        let mut state = VariableState::Normal;
-        let mut var = String::new();
-        let mut final_word = String::new();
-        for c in arg.chars() {
-            if c == '$' {
-                match state {
-                    VariableState::Normal => state = VariableState::Variable,
-                    VariableState::Variable => {
-                        if let Some(val) = variables.get(&var) {
-                            final_word.push_str(val);
-                        }
-                        var = String::new();
-                    }
-                    VariableState::VariableBrace => {
-                        anyhow::bail!("parse error: unexpected token near `$`")
-                    }
-                }
-            } else if c == '{' {
-                match state {
-                    VariableState::Variable => state = VariableState::VariableBrace,
-                    _ => anyhow::bail!("parse error: unexpected token near `{{`"),
-                }
-            } else if c == '}' {
-                match state {
-                    VariableState::VariableBrace => {
-                        if let Some(val) = variables.get(&var) {
-                            final_word.push_str(val);
-                        }
-                        var = String::new();
-                        state = VariableState::Normal;
-                    }
-                    _ => anyhow::bail!("parse error: unexpected token near `}}`"),
-                }
-            } else {
-                match state {
-                    VariableState::Normal => final_word.push(c),
-                    _ => var.push(c),
-                }
+    fn parse_while(&mut self, until: bool) -> anyhow::Result<CompoundCommand> {
+        self.pos += 1;
+        let condition = self.parse_list(true)?;
+        let body = self.parse_do_group()?;
+        Ok(CompoundCommand::While {
+            condition,
+            body,
+            until,
+        })
+    }
+
+    fn parse_do_group(&mut self) -> anyhow::Result<List> {
+        self.skip_newlines();
+        self.expect_reserved("do")?;
+        let body = self.parse_list(true)?;
+        self.expect_reserved("done")?;
+        Ok(body)
+    }
+
+    fn parse_for(&mut self) -> anyhow::Result<CompoundCommand> {
+        self.pos += 1;
+        if let Some(Token::Arithmetic(expression)) = self.peek() {
+            let parts: Vec<String> = expression.split(';').map(String::from).collect();
+            let Ok([init, condition, step]) = <[String; 3]>::try_from(parts) else {
+                anyhow::bail!(
+                    "syntax error: `(({}))': expected three expressions",
+                    expression
+                )
+            };
+            self.pos += 1;
+            if self.peek() == Some(&Token::Operator(";")) {
+                self.pos += 1;
             }
+            let body = self.parse_do_group()?;
+            return Ok(CompoundCommand::ArithmeticFor {
+                init,
+                condition,
+                step,
+                body,
+            });
         }
-        anyhow::ensure!(
-            state != VariableState::VariableBrace,
-            "parse error: unexpected token near `{{`"
-        );
-        if !var.is_empty()
-            && let Some(val) = variables.get(&var)
-        {
-            final_word.push_str(val);
+        let variable = match self.peek() {
+            Some(Token::Word(word)) if !word.quoted && is_name(&word.text()) => word.text(),
+            Some(Token::Word(word)) => anyhow::bail!("`{}': not a valid identifier", word.text()),
+            _ => return Err(self.unexpected()),
+        };
+        self.pos += 1;
+        self.skip_newlines();
+        let mut words = None;
+        if self.peek_reserved("in") {
+            self.pos += 1;
+            let mut list = Vec::new();
+            while let Some(Token::Word(word)) = self.peek() {
+                list.push(word.clone());
+                self.pos += 1;
+            }
+            words = Some(list);
+            match self.peek() {
+                Some(Token::Operator(";")) | Some(Token::Newline) => self.pos += 1,
+                _ => return Err(self.unexpected()),
+            }
+        } else if self.peek() == Some(&Token::Operator(";")) {
+            self.pos += 1;
         }
-        if !final_word.is_empty() {
-            res.push(final_word);
+        let body = self.parse_do_group()?;
+        Ok(CompoundCommand::For {
+            variable,
+            words,
+            body,
+        })
+    }
+
+    fn parse_case(&mut self) -> anyhow::Result<CompoundCommand> {
+        self.pos += 1;
+        let Some(Token::Word(word)) = self.peek() else {
+            return Err(self.unexpected());
+        };
+        let word = word.clone();
+        self.pos += 1;
+        self.skip_newlines();
+        self.expect_reserved("in")?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_reserved("esac") {
+                self.pos += 1;
+                break;
+            }
+            if self.peek() == Some(&Token::Operator("(")) {
+                self.pos += 1;
+            }
+            let mut patterns = Vec::new();
+            loop {
+                let Some(Token::Word(pattern)) = self.peek() else {
+                    return Err(self.unexpected());
+                };
+                patterns.push(pattern.clone());
+                self.pos += 1;
+                if self.peek() != Some(&Token::Operator("|")) {
+                    break;
+                }
+                self.pos += 1;
+            }
+            self.expect_operator(")")?;
+            let body = self.parse_optional_list()?;
+            let terminator = match self.peek() {
+                Some(Token::Operator(";;")) => CaseTerminator::Break,
+                Some(Token::Operator(";&")) => CaseTerminator::FallThrough,
+                Some(Token::Operator(";;&")) => CaseTerminator::Continue,
+                _ if self.peek_reserved("esac") => CaseTerminator::Break,
+                _ => return Err(self.unexpected()),
+            };
+            if !self.peek_reserved("esac") {
+                self.pos += 1;
+            }
+            items.push(CaseItem {
+                patterns,
+                body,
+                terminator,
+            });
         }
+        Ok(CompoundCommand::Case { word, items })
     }
-    Ok(res)
 }
@@ -0,0 +1,228 @@
+use crate::lexer::{Quoting, Word};
+
+/// A pattern character and whether it was quoted, in which case it only matches itself.
+pub type PatternChar = (char, bool);
+
+/// An `extglob` group's operator, its alternatives and the rest of the pattern after it.
+type ExtendedGroup<'a> = (char, Vec<&'a [PatternChar]>, &'a [PatternChar]);
+
+pub fn pattern_from_word(word: &Word) -> Vec<PatternChar> {
+    word.chars
+        .iter()
+        .map(|&(c, q)| (c, q == Quoting::Double || q == Quoting::Literal))
+        .collect()
+}
+
+/// Whether a pattern has unquoted wildcards, counting the groups of `extglob`.
+pub fn has_wildcards(pattern: &[PatternChar], extglob: bool) -> bool {
+    pattern
+        .iter()
+        .enumerate()
+        .any(|(i, &(c, quoted))| match (c, quoted) {
+            ('*' | '?', false) => true,
+            ('[', false) => bracket_match(&pattern[i..], '\0').is_some(),
+            ('+' | '@' | '!', false) => extglob && extended_group(&pattern[i..]).is_some(),
+            _ => false,
+        })
+}
+
+/// Lowercases a pattern, for matching without regard to case.
+pub fn lowercase(pattern: &[PatternChar]) -> Vec<PatternChar> {
+    pattern
+        .iter()
+        .flat_map(|&(c, quoted)| c.to_lowercase().map(move |c| (c, quoted)))
+        .collect()
+}
+
+/// Matches `text` against a glob pattern made of `*`, `?` and `[...]` bracket expressions, and
+/// with `extglob` the groups `?(...)`, `*(...)`, `+(...)`, `@(...)` and `!(...)`.
+pub fn pattern_matches(pattern: &[PatternChar], text: &str, extglob: bool) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    if extglob && (0..pattern.len()).any(|i| extended_group(&pattern[i..]).is_some()) {
+        return extended_matches(pattern, &text);
+    }
+    let (mut pi, mut ti) = (0, 0);
+    let mut star = None; // pattern index after the last `*` and the text index it resumes from
+    while ti < text.len() {
+        if let Some(&(c, quoted)) = pattern.get(pi) {
+            match (c, quoted) {
+                ('*', false) => {
+                    pi += 1;
+                    star = Some((pi, ti));
+                    continue;
+                }
+                ('?', false) => {
+                    pi += 1;
+                    ti += 1;
+                    continue;
+                }
+                ('[', false) if bracket_match(&pattern[pi..], text[ti]).is_some() => {
+                    if let Some((true, len)) = bracket_match(&pattern[pi..], text[ti]) {
+                        pi += len;
+                        ti += 1;
+                        continue;
+                    }
+                }
+                _ if c == text[ti] => {
+                    pi += 1;
+                    ti += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        match star {
+            Some((star_pi, star_ti)) => {
+                pi = star_pi;
+                ti = star_ti + 1;
+                star = Some((star_pi, ti));
+            }
+            None => return false,
+        }
+    }
+    pattern[pi..].iter().all(|&p| p == ('*', false))
+}
+
+/// Matches by backtracking, for patterns with `extglob` groups.
+fn extended_matches(pattern: &[PatternChar], text: &[char]) -> bool {
+    let Some(&(c, quoted)) = pattern.first() else {
+        return text.is_empty();
+    };
+    if let Some((operator, alternatives, rest)) = extended_group(pattern) {
+        let any_matches = |text: &[char]| {
+            alternatives
+                .iter()
+                .any(|alternative| extended_matches(alternative, text))
+        };
+        let mut splits = 0..=text.len();
+        return match operator {
+            '?' => {
+                extended_matches(rest, text)
+                    || splits.any(|k| any_matches(&text[..k]) && extended_matches(rest, &text[k..]))
+            }
+            '@' => splits.any(|k| any_matches(&text[..k]) && extended_matches(rest, &text[k..])),
+            '!' => splits.any(|k| !any_matches(&text[..k]) && extended_matches(rest, &text[k..])),
+            '*' => repeated_matches(&alternatives, rest, text),
+            _ => (1..=text.len()).any(|k| {
+                any_matches(&text[..k]) && repeated_matches(&alternatives, rest, &text[k..])
+            }),
+        };
+    }
+    let Some((&first, others)) = text.split_first() else {
+        return pattern.iter().all(|&p| p == ('*', false));
+    };
+    match (c, quoted) {
+        ('*', false) => (0..=text.len()).any(|k| extended_matches(&pattern[1..], &text[k..])),
+        ('?', false) => extended_matches(&pattern[1..], others),
+        ('[', false) => match bracket_match(pattern, first) {
+            Some((true, len)) => extended_matches(&pattern[len..], others),
+            Some((false, _)) => false,
+            None => c == first && extended_matches(&pattern[1..], others),
+        },
+        _ => c == first && extended_matches(&pattern[1..], others),
+    }
+}
+
+/// Matches any number of the alternatives of a `*(...)` group followed by the rest of the pattern.
+fn repeated_matches(alternatives: &[&[PatternChar]], rest: &[PatternChar], text: &[char]) -> bool {
+    extended_matches(rest, text)
+        || (1..=text.len()).any(|k| {
+            alternatives
+                .iter()
+                .any(|alternative| extended_matches(alternative, &text[..k]))
+                && repeated_matches(alternatives, rest, &text[k..])
+        })
+}
+
+/// Splits an `extglob` group at the start of the pattern into its operator, its `|`-separated
+/// alternatives and the rest of the pattern. Returns `None` when the pattern does not start with
+/// a closed group.
+fn extended_group(pattern: &[PatternChar]) -> Option<ExtendedGroup<'_>> {
+    let [(operator, false), ('(', false), ..] = pattern else {
+        return None;
+    };
+    if !"?*+@!".contains(*operator) {
+        return None;
+    }
+    let mut alternatives = Vec::new();
+    let mut start = 2;
+    let mut depth = 0;
+    for (i, &(c, quoted)) in pattern.iter().enumerate().skip(2) {
+        match (c, quoted) {
+            ('(', false) => depth += 1,
+            (')', false) if depth > 0 => depth -= 1,
+            (')', false) => {
+                alternatives.push(&pattern[start..i]);
+                return Some((*operator, alternatives, &pattern[i + 1..]));
+            }
+            ('|', false) if depth == 0 => {
+                alternatives.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Matches `c` against the bracket expression at the start of `pattern`, returning whether it
+/// matched and the length of the expression, or `None` if the `[` is not closed.
+fn bracket_match(pattern: &[PatternChar], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some(('!' | '^', false)));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &(member, quoted) = pattern.get(i)?;
+        if member == ']' && !quoted && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if member == '[' && !quoted && matches!(pattern.get(i + 1), Some((':', false))) {
+            let class: String = pattern[i + 2..]
+                .iter()
+                .map(|(c, _)| *c)
+                .take_while(|c| *c != ':')
+                .collect();
+            if matches!(
+                pattern.get(i + 2 + class.len()..i + 4 + class.len()),
+                Some([(':', _), (']', _)])
+            ) {
+                matched |= class_matches(&class, c);
+                i += class.len() + 4;
+                continue;
+            }
+        }
+        if matches!(pattern.get(i + 1), Some(('-', false)))
+            && let Some(&(end, _)) = pattern.get(i + 2)
+            && end != ']'
+        {
+            matched |= member <= c && c <= end;
+            i += 3;
+        } else {
+            matched |= member == c;
+            i += 1;
+        }
+    }
+}
+
+fn class_matches(class: &str, c: char) -> bool {
+    match class {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => c.is_ascii_graphic(),
+        "lower" => c.is_lowercase(),
+        "print" => c.is_ascii_graphic() || c == ' ',
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
@@ -0,0 +1,474 @@
+/// Output of a `printf` format, with messages for arguments that were not valid numbers.
+pub struct Formatted {
+    pub output: String,
+    pub errors: Vec<String>,
+}
+
+/// Formats the arguments as `printf` does. The format is reused while arguments remain.
+pub fn format(format: &str, args: &[String]) -> Formatted {
+    let format: Vec<char> = format.chars().collect();
+    let mut formatter = Formatter {
+        args,
+        next: 0,
+        output: String::new(),
+        errors: Vec::new(),
+    };
+    loop {
+        let start = formatter.next;
+        if !formatter.format_once(&format) {
+            break;
+        }
+        // Stop once every argument is used, or when the format takes none.
+        if formatter.next >= args.len() || formatter.next == start {
+            break;
+        }
+    }
+    Formatted {
+        output: formatter.output,
+        errors: formatter.errors,
+    }
+}
+
+/// Expands the backslash escapes of `echo -e` and `%b`. Only `%b` takes octal escapes without
+/// a leading zero (`bare_octal`). Returns false when `\c` asks to stop all further output.
+pub fn expand_escapes(text: &str, output: &mut String, bare_octal: bool) -> bool {
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '\\' || i + 1 == chars.len() {
+            output.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        match chars[i] {
+            'c' => return false,
+            '0' => {
+                // `\0nnn` takes up to three octal digits after the zero.
+                let (value, len) = digits(&chars[i + 1..], 8, 3);
+                push_byte(output, value);
+                i += 1 + len;
+            }
+            '1'..='7' if !bare_octal => {
+                output.push('\\');
+                output.push(chars[i]);
+                i += 1;
+            }
+            _ => i += escape(&chars[i..], output),
+        }
+    }
+    true
+}
+
+/// Expands the escape whose first character (after the backslash) starts `chars`. Returns the
+/// number of characters consumed.
+fn escape(chars: &[char], output: &mut String) -> usize {
+    let simple = match chars[0] {
+        'a' => Some('\x07'),
+        'b' => Some('\x08'),
+        'e' | 'E' => Some('\x1b'),
+        'f' => Some('\x0c'),
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        'v' => Some('\x0b'),
+        '\\' => Some('\\'),
+        '"' => Some('"'),
+        '\'' => Some('\''),
+        '?' => Some('?'),
+        _ => None,
+    };
+    if let Some(c) = simple {
+        output.push(c);
+        return 1;
+    }
+    let (radix, max_len) = match chars[0] {
+        '0'..='7' => (8, 3),
+        'x' => (16, 2),
+        'u' => (16, 4),
+        'U' => (16, 8),
+        c => {
+            output.push('\\');
+            output.push(c);
+            return 1;
+        }
+    };
+    let skip = usize::from(radix == 16);
+    let (value, len) = digits(&chars[skip..], radix, max_len);
+    if len == 0 {
+        output.push('\\');
+        output.push(chars[0]);
+        return 1;
+    }
+    match chars[0] {
+        'u' | 'U' => output.push(char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER)),
+        _ => push_byte(output, value),
+    }
+    skip + len
+}
+
+/// Parses up to `max_len` digits in `radix`, returning the value and the number of digits.
+fn digits(chars: &[char], radix: u32, max_len: usize) -> (u32, usize) {
+    let mut value = 0;
+    let mut len = 0;
+    while len < max_len
+        && let Some(digit) = chars.get(len).and_then(|c| c.to_digit(radix))
+    {
+        value = value * radix + digit;
+        len += 1;
+    }
+    (value, len)
+}
+
+fn push_byte(output: &mut String, value: u32) {
+    output.push(char::from_u32(value & 0xff).unwrap_or(char::REPLACEMENT_CHARACTER));
+}
+
+/// Quotes `text` so that the shell reads it back as a single word, as `%q` does.
+pub fn quote(text: &str) -> String {
+    if text.is_empty() {
+        return String::from("''");
+    }
+    if text.chars().any(|c| c.is_control()) {
+        let mut quoted = String::from("$'");
+        for c in text.chars() {
+            match c {
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                '\x1b' => quoted.push_str("\\E"),
+                '\'' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                c if c.is_control() => quoted.push_str(&format!("\\{:03o}", c as u32)),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('\'');
+        return quoted;
+    }
+    let mut quoted = String::new();
+    for c in text.chars() {
+        if !(c.is_alphanumeric() || "_-./,:=+@%^".contains(c)) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+struct Formatter<'a> {
+    args: &'a [String],
+    next: usize,
+    output: String,
+    errors: Vec<String>,
+}
+
+/// Flags, width and precision of a conversion specification.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Formatter<'_> {
+    fn next_arg(&mut self) -> Option<&str> {
+        let arg = self.args.get(self.next)?;
+        self.next += 1;
+        Some(arg)
+    }
+
+    /// Formats the whole format string once. Returns false when `%b` hit a `\c`.
+    fn format_once(&mut self, format: &[char]) -> bool {
+        let mut i = 0;
+        while i < format.len() {
+            match format[i] {
+                '\\' if i + 1 < format.len() => {
+                    i += 1 + escape(&format[i + 1..], &mut self.output);
+                }
+                '%' if format.get(i + 1) == Some(&'%') => {
+                    self.output.push('%');
+                    i += 2;
+                }
+                '%' => {
+                    let Some(len) = self.conversion(&format[i + 1..]) else {
+                        return false;
+                    };
+                    i += 1 + len;
+                }
+                c => {
+                    self.output.push(c);
+                    i += 1;
+                }
+            }
+        }
+        true
+    }
+
+    /// Formats the conversion specification at the start of `chars`, returning its length, or
+    /// `None` when `%b` hit a `\c`.
+    fn conversion(&mut self, chars: &[char]) -> Option<usize> {
+        let mut spec = Spec::default();
+        let mut i = 0;
+        while let Some(&c) = chars.get(i) {
+            match c {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        if chars.get(i) == Some(&'*') {
+            let width = self.integer_arg();
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+            i += 1;
+        } else {
+            let (width, len) = digits(&chars[i..], 10, usize::MAX);
+            spec.width = width as usize;
+            i += len;
+        }
+        if chars.get(i) == Some(&'.') {
+            i += 1;
+            if chars.get(i) == Some(&'*') {
+                spec.precision = usize::try_from(self.integer_arg()).ok();
+                i += 1;
+            } else {
+                let (precision, len) = digits(&chars[i..], 10, usize::MAX);
+                spec.precision = Some(precision as usize);
+                i += len;
+            }
+        }
+        // Length modifiers are accepted and ignored.
+        while matches!(chars.get(i), Some('h' | 'l' | 'L' | 'j' | 'z' | 't')) {
+            i += 1;
+        }
+        let Some(&conversion) = chars.get(i) else {
+            self.output.push('%');
+            self.output.extend(&chars[..i]);
+            return Some(i);
+        };
+        match conversion {
+            's' => {
+                let arg = self.next_arg().unwrap_or("").to_string();
+                let text = match spec.precision {
+                    Some(precision) => arg.chars().take(precision).collect(),
+                    None => arg,
+                };
+                self.pad(&spec, "", &text, false);
+            }
+            'b' => {
+                let arg = self.next_arg().unwrap_or("").to_string();
+                let mut text = String::new();
+                let complete = expand_escapes(&arg, &mut text, true);
+                self.pad(&spec, "", &text, false);
+                if !complete {
+                    return None;
+                }
+            }
+            'q' => {
+                let arg = self.next_arg().unwrap_or("").to_string();
+                self.pad(&spec, "", &quote(&arg), false);
+            }
+            'c' => {
+                let arg = self.next_arg().unwrap_or("").to_string();
+                self.pad(&spec, "", &arg.chars().take(1).collect::<String>(), false);
+            }
+            'd' | 'i' => {
+                let value = self.integer_arg();
+                let sign = sign(value < 0, &spec);
+                let digits = integer_digits(value.unsigned_abs().to_string(), &spec);
+                self.pad(&spec, sign, &digits, spec.precision.is_none());
+            }
+            'u' | 'o' | 'x' | 'X' => {
+                let value = self.integer_arg() as u64;
+                let (digits, prefix) = match conversion {
+                    'u' => (value.to_string(), ""),
+                    'o' => (format!("{value:o}"), "0"),
+                    'x' => (format!("{value:x}"), "0x"),
+                    _ => (format!("{value:X}"), "0X"),
+                };
+                let mut digits = integer_digits(digits, &spec);
+                let prefix = match (spec.alternate && value != 0, conversion) {
+                    (false, _) | (_, 'u') => "",
+                    (true, 'o') => {
+                        if !digits.starts_with('0') {
+                            digits.insert(0, '0');
+                        }
+                        ""
+                    }
+                    (true, _) => prefix,
+                };
+                self.pad(&spec, prefix, &digits, spec.precision.is_none());
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = self.float_arg();
+                let precision = spec.precision.unwrap_or(6);
+                let text = match conversion {
+                    'f' | 'F' => format!("{:.*}", precision, value.abs()),
+                    'e' | 'E' => exponential(value.abs(), precision),
+                    _ => general(value.abs(), precision, spec.alternate),
+                };
+                let text = match conversion {
+                    'F' | 'E' | 'G' => text.to_uppercase(),
+                    _ => text,
+                };
+                let sign = sign(value.is_sign_negative() && value != 0.0, &spec);
+                self.pad(&spec, sign, &text, value.is_finite());
+            }
+            c => {
+                self.errors
+                    .push(format!("printf: `{}': invalid format character", c));
+                return None;
+            }
+        }
+        Some(i + 1)
+    }
+
+    /// Writes a converted value padded to the field width. Zero padding goes between the sign
+    /// or prefix and the digits.
+    fn pad(&mut self, spec: &Spec, prefix: &str, body: &str, zero_pad: bool) {
+        let len = prefix.chars().count() + body.chars().count();
+        let fill = spec.width.saturating_sub(len);
+        if spec.left {
+            self.output.push_str(prefix);
+            self.output.push_str(body);
+            self.output.extend(std::iter::repeat_n(' ', fill));
+        } else if spec.zero && zero_pad {
+            self.output.push_str(prefix);
+            self.output.extend(std::iter::repeat_n('0', fill));
+            self.output.push_str(body);
+        } else {
+            self.output.extend(std::iter::repeat_n(' ', fill));
+            self.output.push_str(prefix);
+            self.output.push_str(body);
+        }
+    }
+
+    /// The next argument as an integer: decimal, octal with a leading `0`, hexadecimal with
+    /// `0x`, or the code of the character after a leading quote.
+    fn integer_arg(&mut self) -> i64 {
+        let Some(arg) = self.next_arg().map(str::to_string) else {
+            return 0;
+        };
+        if let Some(c) = arg.strip_prefix(['\'', '"']) {
+            return c.chars().next().map_or(0, |c| c as i64);
+        }
+        let text = arg.trim_start();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (radix, digits) =
+            if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+                (16, hex)
+            } else if digits.len() > 1 && digits.starts_with('0') {
+                (8, &digits[1..])
+            } else {
+                (10, digits)
+            };
+        let valid: String = digits.chars().take_while(|c| c.is_digit(radix)).collect();
+        let value = i64::from_str_radix(&valid, radix).unwrap_or(0);
+        if valid.is_empty() || valid.len() != digits.len() {
+            self.errors.push(format!("printf: {}: invalid number", arg));
+        }
+        if negative { -value } else { value }
+    }
+
+    fn float_arg(&mut self) -> f64 {
+        let Some(arg) = self.next_arg().map(str::to_string) else {
+            return 0.0;
+        };
+        if let Some(c) = arg.strip_prefix(['\'', '"']) {
+            return c.chars().next().map_or(0.0, |c| c as u32 as f64);
+        }
+        match arg.trim().parse::<f64>() {
+            Ok(value) => value,
+            Err(_) => {
+                self.errors.push(format!("printf: {}: invalid number", arg));
+                0.0
+            }
+        }
+    }
+}
+
+fn sign(negative: bool, spec: &Spec) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+/// Pads integer digits with zeros to the precision, the minimum number of digits.
+fn integer_digits(digits: String, spec: &Spec) -> String {
+    match spec.precision {
+        Some(0) if digits == "0" => String::new(),
+        Some(precision) if precision > digits.len() => {
+            format!("{}{}", "0".repeat(precision - digits.len()), digits)
+        }
+        _ => digits,
+    }
+}
+
+/// Formats like C's `%e`, with at least two exponent digits: `1.500000e+02`.
+fn exponential(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let Some((mantissa, exponent)) = text.split_once('e') else {
+        return text; // infinity or NaN
+    };
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+/// Formats like C's `%g`: `%e` for very small or large exponents, `%f` otherwise, without
+/// trailing zeros unless `alternate`.
+fn general(value: f64, precision: usize, alternate: bool) -> String {
+    if !value.is_finite() {
+        return format!("{value}");
+    }
+    let precision = precision.max(1);
+    let exponent = match value {
+        0.0 => 0,
+        _ => {
+            let text = format!("{:.*e}", precision - 1, value);
+            text.split_once('e')
+                .and_then(|(_, exponent)| exponent.parse::<i32>().ok())
+                .unwrap_or(0)
+        }
+    };
+    let text = if exponent < -4 || exponent >= precision as i32 {
+        exponential(value, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+    };
+    if alternate {
+        return text;
+    }
+    let (mantissa, exponent) = match text.split_once('e') {
+        Some((mantissa, exponent)) => (mantissa.to_string(), format!("e{exponent}")),
+        None => (text, String::new()),
+    };
+    let mantissa = match mantissa.contains('.') {
+        true => mantissa
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string(),
+        false => mantissa,
+    };
+    mantissa + &exponent
+}
@@ -8,13 +8,19 @@ use std::{
 use anyhow::Result;
 use rustyline::{completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator};
 
+use crate::{builtin, parser::Aliases};
+
 pub struct Helper {
     pub completers: HashMap<String, String>,
+    pub aliases: Aliases,
 }
 
 impl Helper {
     pub fn new(completers: HashMap<String, String>) -> Self {
-        Helper { completers }
+        Helper {
+            completers,
+            aliases: Aliases::new(),
+        }
     }
 
     fn register_completions(&self, line: &str, pos: usize) -> Result<(usize, Vec<String>)> {
@@ -57,7 +63,7 @@ impl Helper {
         Ok((line.len() - word.len(), candidates))
     }
 
-    fn command_completions(line: &str) -> (usize, Vec<String>) {
+    fn command_completions(&self, line: &str) -> (usize, Vec<String>) {
         if line.ends_with(' ') {
             return (0, Vec::new());
         }
@@ -65,11 +71,10 @@ impl Helper {
         let (lhs, pattern) = line.rsplit_once(' ').unwrap_or(("", line));
         let mut candidates = HashSet::new();
 
-        let builtins = [
-            "echo", "exit", "cd", "pwd", "type", "history", "jobs", "complete", "declare",
-        ];
-
-        for cmd in builtins.into_iter() {
+        for cmd in builtin::NAMES
+            .into_iter()
+            .chain(self.aliases.keys().map(String::as_str))
+        {
             if cmd.starts_with(pattern) {
                 candidates.insert(cmd.to_string());
             }
@@ -148,7 +153,7 @@ impl Completer for Helper {
         }
 
         let line = &line[..pos];
-        let mut completion = Self::command_completions(line);
+        let mut completion = self.command_completions(line);
         if completion.1.is_empty() {
             completion = Self::directory_completions(line);
         }
@@ -13,6 +13,7 @@ pub struct History {
     max_length: usize,
     ignore_dup: bool,
     ignore_space: bool,
+    session_start: usize, // index of the first command entered in this session
 }
 
 impl History {
@@ -39,6 +40,23 @@ impl History {
         Ok(())
     }
 
+    /// Marks the commands so far as read from the history file, not entered in this session.
+    pub fn start_session(&mut self) {
+        self.session_start = self.commands.len();
+    }
+
+    /// Appends the commands entered in this session to the file, as with `histappend`.
+    pub fn append_session_to_file(&self, file_name: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_name)?;
+        for line in self.commands.iter().skip(self.session_start) {
+            writeln!(file, "{}", line)?;
+        }
+        Ok(())
+    }
+
     pub fn write_to_file(&self, file_name: &str) -> io::Result<()> {
         let mut file = OpenOptions::new()
             .create(true)
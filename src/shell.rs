@@ -1,125 +1,1362 @@
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
     io::{self, Write},
+    os::{fd::AsRawFd, unix::process::CommandExt},
+    path::Path,
+    process,
     str::FromStr,
 };
 
-use rustyline::Editor;
+use anyhow::anyhow;
+use regex::Regex;
+use rustyline::{Editor, error::ReadlineError};
 
 use crate::{
-    builtin::{self, Builtin},
-    command::find_excutable,
-    job::Jobs,
-    parser::{self, args_expansion},
+    arithmetic,
+    ast::{
+        AndOr, CaseTerminator, Command, CompoundCommand, Conditional, Connector, Functions, List,
+        Pipeline, Redirect, RedirectKind, SimpleCommand,
+    },
+    builtin::{self, Builtin, quote_word},
+    command::{
+        SavedFds, exit_child, find_excutable, fork, io_error_message, path_executables,
+        similar_command, wait_pid,
+    },
+    condition::{file_test, integer_test, unary_test},
+    expansion::{expand_arithmetic, expand_pattern, expand_subscript, expand_word, expand_words},
+    job::{Job, JobStatus, Jobs},
+    lexer::{self, Assignment, Incomplete, Quoting, Token, Word},
+    options::Options,
+    parser::{self, Aliases, Parser},
+    pattern::pattern_matches,
     readline::{helper::Helper, history::History},
+    signal::{self, Trap, Traps},
+    variable::{Attributes, Unbound, Value, Variables},
 };
 
+/// Function called with a command that is not found, and its arguments.
+const NOT_FOUND_HANDLER: &str = "command_not_found_handle";
+
+/// How control leaves a command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flow {
+    Normal,
+    Break(u32),
+    Continue(u32),
+    Return(i32),
+    Exit(i32),
+}
+
 pub struct Shell<'a> {
     editor: &'a mut Editor<Helper, History>,
     jobs: Jobs,
-    variables: HashMap<String, String>,
+    variables: Variables,
+    options: Options,
+    functions: Functions,
+    traps: Traps,
+    loop_depth: u32,
+    conditions: u32,   // depth of `if`, `while` and `until` conditions being executed
+    jobs_listed: bool, // `jobs` already reported finished jobs
+    exit_warned: bool, // `exit` was refused because of running jobs, with `checkjobs`
+    in_trap: bool,     // trapped commands are running, which no other trap interrupts
 }
 
 impl<'a> Shell<'a> {
     pub fn new(
         editor: &'a mut Editor<Helper, History>,
         jobs: Jobs,
-        variables: HashMap<String, String>,
+        variables: Variables,
+        options: Options,
     ) -> Self {
+        signal::init(variables.is_interactive());
         Self {
             editor,
             jobs,
             variables,
+            options,
+            functions: Functions::new(),
+            traps: Traps::new(),
+            loop_depth: 0,
+            conditions: 0,
+            jobs_listed: false,
+            exit_warned: false,
+            in_trap: false,
         }
     }
 
-    pub fn run(&mut self) -> anyhow::Result<()> {
-        loop {
-            let input = self.editor.readline("$ ")?;
-            let commands = match parser::commands_from_input(input) {
-                Ok(commands) => commands,
-                Err(e) => {
-                    eprintln!("{e}");
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Reads and executes commands from the editor until `exit`, returning the exit status.
+    pub fn run(&mut self) -> anyhow::Result<i32> {
+        'prompt: loop {
+            if let Flow::Exit(code) = self.run_pending_traps() {
+                return Ok(self.exiting(code));
+            }
+            let mut input = match self.editor.readline("$ ") {
+                Ok(input) => input,
+                Err(ReadlineError::Eof) => return Ok(self.exiting(self.variables.last_status)),
+                Err(ReadlineError::Interrupted) => {
+                    self.variables.last_status = 130;
                     continue;
                 }
+                Err(e) => return Err(e.into()),
             };
-            let total_commands = commands.len();
-            let mut command_io = None;
-            let mut has_job_builtin = false;
-            self.jobs.update_status();
-
-            for (idx, mut cmd) in commands.into_iter().enumerate() {
-                cmd.args = match args_expansion(cmd.args, &self.variables) {
-                    Ok(args) => args,
+            let commands = loop {
+                match parser::parse(&input, &self.aliases(), self.options.shopt("extglob")) {
+                    Ok(commands) => break commands,
+                    Err(e) if e.is::<Incomplete>() => {
+                        // Keep reading until the command is complete.
+                        input.push('\n');
+                        match self.editor.readline("> ") {
+                            Ok(line) => input.push_str(&line),
+                            Err(ReadlineError::Interrupted) => {
+                                self.variables.last_status = 130;
+                                continue 'prompt;
+                            }
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
                     Err(e) => {
                         eprintln!("{e}");
+                        self.variables.last_status = 2;
+                        break Vec::new();
+                    }
+                }
+            };
+            if self.options.option("verbose") {
+                eprintln!("{input}");
+            }
+            self.jobs.update_status();
+            self.jobs_listed = false;
+            let exit_warned = self.exit_warned;
+            for list in &commands {
+                if let Flow::Exit(code) = self.execute(list) {
+                    // With `checkjobs`, exiting takes a second `exit` right after the warning.
+                    if self.options.shopt("checkjobs") && !exit_warned && self.jobs.has_running() {
+                        eprintln!("There are running jobs.");
+                        if let Ok(jobs) = builtin::jobs(self.jobs.value()) {
+                            println!("{jobs}");
+                        }
+                        self.exit_warned = true;
                         break;
                     }
+                    if self.options.shopt("huponexit") {
+                        self.jobs.hang_up();
+                    }
+                    return Ok(self.exiting(code));
+                }
+            }
+            if exit_warned {
+                self.exit_warned = false;
+            }
+            if !self.jobs_listed {
+                self.jobs.print_done();
+            }
+            self.jobs.clean_up();
+        }
+    }
+
+    /// Executes a script file command by command, returning the exit status.
+    pub fn run_script(&mut self, path: &str) -> anyhow::Result<i32> {
+        let script = fs::read_to_string(path)?;
+        let mut parser = match Parser::new(&script, self.options.shopt("extglob")) {
+            Ok(parser) => parser,
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                return Ok(2);
+            }
+        };
+        loop {
+            match parser.next_command(&self.aliases(), self.options.shopt("extglob")) {
+                Ok(Some(list)) => {
+                    if self.options.option("verbose") {
+                        eprintln!("{list}");
+                    }
+                    if let Flow::Exit(code) = self.execute(&list) {
+                        return Ok(self.exiting(code));
+                    }
+                }
+                Ok(None) => return Ok(self.exiting(self.variables.last_status)),
+                Err(e) => {
+                    eprintln!("{path}: {e}");
+                    return Ok(2);
+                }
+            }
+        }
+    }
+
+    /// Aliases to expand while parsing, none unless `expand_aliases` is set.
+    fn aliases(&self) -> Aliases {
+        match self.options.shopt("expand_aliases") {
+            true => self.helper().aliases.clone(),
+            false => Aliases::new(),
+        }
+    }
+
+    /// Runs the `EXIT` trap as the shell exits with `code`, returning the final status.
+    fn exiting(&mut self, code: i32) -> i32 {
+        self.variables.last_status = code;
+        match self.run_trap(Trap::Exit) {
+            Flow::Exit(code) => code,
+            _ => code,
+        }
+    }
+
+    /// Runs the commands trapped on a condition. They keep `$?` unless they exit the shell.
+    fn run_trap(&mut self, condition: Trap) -> Flow {
+        let command = match self.traps.get(&condition) {
+            Some(command) if !command.is_empty() && !self.in_trap => command.clone(),
+            _ => return Flow::Normal,
+        };
+        let lists = match parser::parse(&command, &self.aliases(), self.options.shopt("extglob")) {
+            Ok(lists) => lists,
+            Err(e) => {
+                eprintln!("{e}");
+                return Flow::Normal;
+            }
+        };
+        let status = self.variables.last_status;
+        self.in_trap = true;
+        let mut flow = Flow::Normal;
+        for list in &lists {
+            flow = self.execute(list);
+            if flow != Flow::Normal {
+                break;
+            }
+        }
+        self.in_trap = false;
+        self.variables.last_status = status;
+        flow
+    }
+
+    /// Runs the commands trapped on the signals received since the last check.
+    fn run_pending_traps(&mut self) -> Flow {
+        if self.in_trap {
+            return Flow::Normal;
+        }
+        for number in signal::take_pending() {
+            if let Flow::Exit(code) = self.run_trap(Trap::Signal(number)) {
+                return Flow::Exit(code);
+            }
+        }
+        Flow::Normal
+    }
+
+    /// Prepares a forked child, which keeps ignored signals but not the other traps.
+    fn enter_child(&mut self) {
+        signal::reset_in_child();
+        self.traps.retain(|_, command| command.is_empty());
+    }
+
+    fn helper(&self) -> &Helper {
+        self.editor.helper().expect("the editor has a helper")
+    }
+
+    fn helper_mut(&mut self) -> &mut Helper {
+        self.editor.helper_mut().expect("the editor has a helper")
+    }
+
+    /// Executes one complete command, reporting errors that abort it.
+    fn execute(&mut self, list: &List) -> Flow {
+        match self.execute_list(list) {
+            Ok(Flow::Exit(code)) => Flow::Exit(code),
+            Ok(_) => Flow::Normal,
+            Err(e) => self.command_failed(e),
+        }
+    }
+
+    fn execute_list(&mut self, list: &List) -> anyhow::Result<Flow> {
+        for item in &list.items {
+            if item.is_background_job {
+                self.run_background_job(&item.and_or)?;
+                continue;
+            }
+            let flow = self.execute_and_or(&item.and_or)?;
+            if flow != Flow::Normal {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn execute_and_or(&mut self, and_or: &AndOr) -> anyhow::Result<Flow> {
+        let mut flow = self.execute_pipeline(&and_or.first)?;
+        let mut last = &and_or.first; // the last pipeline that ran
+        for (connector, pipeline) in &and_or.rest {
+            if flow != Flow::Normal {
+                break;
+            }
+            let succeeded = self.variables.last_status == 0;
+            if succeeded == (*connector == Connector::And) {
+                flow = self.execute_pipeline(pipeline)?;
+                last = pipeline;
+            }
+        }
+        // The `ERR` trap and `set -e` act on a failure, unless it was tested by `!`, `&&`, `||`
+        // or a condition.
+        let final_pipeline = and_or
+            .rest
+            .last()
+            .map_or(&and_or.first, |(_, pipeline)| pipeline);
+        if flow == Flow::Normal
+            && self.variables.last_status != 0
+            && self.conditions == 0
+            && !last.negated
+            && std::ptr::eq(last, final_pipeline)
+        {
+            // Like bash, functions do not inherit the `ERR` trap.
+            if !self.variables.in_function()
+                && let Flow::Exit(code) = self.run_trap(Trap::Err)
+            {
+                return Ok(Flow::Exit(code));
+            }
+            if self.options.option("errexit") {
+                return Ok(Flow::Exit(self.variables.last_status));
+            }
+        }
+        Ok(flow)
+    }
+
+    /// Executes the condition of `if`, `while` or `until`, whose failure `set -e` ignores.
+    fn execute_condition(&mut self, condition: &List) -> anyhow::Result<Flow> {
+        self.conditions += 1;
+        let result = self.execute_list(condition);
+        self.conditions -= 1;
+        result
+    }
+
+    fn execute_pipeline(&mut self, pipeline: &Pipeline) -> anyhow::Result<Flow> {
+        let (flow, statuses) = match pipeline.commands.as_slice() {
+            // Compound commands other than subshells leave the statuses of their own pipelines.
+            [command @ Command::Compound(compound, _)]
+                if !matches!(compound, CompoundCommand::Subshell(_)) =>
+            {
+                (self.execute_command(command)?, None)
+            }
+            [command] => {
+                let flow = self.execute_command(command)?;
+                (flow, Some(vec![self.variables.last_status]))
+            }
+            commands => (Flow::Normal, Some(self.run_pipeline(commands)?)),
+        };
+        if let Some(statuses) = statuses {
+            let statuses = statuses.into_iter().map(|status| status.to_string());
+            // Like bash, a readonly `PIPESTATUS` just stops being updated.
+            let _ = self.variables.set(
+                String::from("PIPESTATUS"),
+                Value::Indexed(statuses.enumerate().collect()),
+            );
+        }
+        if pipeline.negated {
+            self.variables.last_status = (self.variables.last_status == 0) as i32;
+        }
+        match self.run_pending_traps() {
+            Flow::Exit(code) => Ok(Flow::Exit(code)),
+            _ => Ok(flow),
+        }
+    }
+
+    /// Runs every command of a pipeline in its own process, connected by pipes, and returns
+    /// their statuses.
+    fn run_pipeline(&mut self, commands: &[Command]) -> anyhow::Result<Vec<i32>> {
+        let mut pids = Vec::new();
+        let mut stdin: Option<io::PipeReader> = None;
+        for (idx, command) in commands.iter().enumerate() {
+            let (reader, writer) = match idx + 1 < commands.len() {
+                true => {
+                    let (reader, writer) = io::pipe()?;
+                    (Some(reader), Some(writer))
+                }
+                false => (None, None),
+            };
+            let mut saved = SavedFds::new();
+            if let Some(input) = &stdin {
+                saved.redirect(0, input.as_raw_fd())?;
+            }
+            if let Some(output) = &writer {
+                saved.redirect(1, output.as_raw_fd())?;
+            }
+            match fork()? {
+                Some(pid) => pids.push(pid),
+                None => {
+                    self.enter_child();
+                    // The redirected descriptors belong to this process now.
+                    std::mem::forget(saved);
+                    drop((reader, writer, stdin));
+                    self.exit_with(command);
+                }
+            }
+            drop(saved);
+            stdin = reader;
+        }
+        let mut statuses = Vec::new();
+        for pid in pids {
+            statuses.push(wait_pid(pid)?);
+        }
+        // With `set -o pipefail`, the last command that failed decides the status.
+        let failed = statuses.iter().rev().find(|status| **status != 0);
+        self.variables.last_status = match (self.options.option("pipefail"), failed) {
+            (true, Some(status)) => *status,
+            _ => statuses.last().copied().unwrap_or(0),
+        };
+        Ok(statuses)
+    }
+
+    /// Runs an and-or list in a forked process without waiting for it.
+    fn run_background_job(&mut self, and_or: &AndOr) -> anyhow::Result<()> {
+        match fork()? {
+            Some(pid) => {
+                let number = self.jobs.new_job_number();
+                if self.variables.is_interactive() {
+                    println!("[{}] {}", number, pid);
+                }
+                self.jobs.push(Job {
+                    pid,
+                    number,
+                    command: and_or.to_string(),
+                    status: JobStatus::Running,
+                });
+                self.variables.last_background_pid = Some(pid);
+                self.variables.last_status = 0;
+            }
+            None => {
+                self.enter_child();
+                match (and_or.rest.is_empty(), and_or.first.commands.as_slice()) {
+                    (true, [command]) if !and_or.first.negated => self.exit_with(command),
+                    _ => {
+                        let result = self.execute_and_or(and_or);
+                        self.exit_child_with(result)
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes a command in a forked child and exits with its status. External programs
+    /// replace the child process instead of running in another one.
+    fn exit_with(&mut self, command: &Command) -> ! {
+        let result = match command {
+            Command::Simple(simple) => self.execute_simple(simple, true),
+            // Already in a child process, so the subshell needs no fork of its own.
+            Command::Compound(CompoundCommand::Subshell(list), redirects) => {
+                match self.apply_redirects(redirects) {
+                    Ok(_saved) => self.execute_list(list),
+                    Err(e) => Err(e),
+                }
+            }
+            command => self.execute_command(command),
+        };
+        self.exit_child_with(result)
+    }
+
+    /// Ends a forked child with the status of what it executed.
+    fn exit_child_with(&mut self, result: anyhow::Result<Flow>) -> ! {
+        let code = match result {
+            Ok(Flow::Exit(code) | Flow::Return(code)) => code,
+            Ok(_) => self.variables.last_status,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        };
+        exit_child(code)
+    }
+
+    fn execute_command(&mut self, command: &Command) -> anyhow::Result<Flow> {
+        match command {
+            Command::Simple(simple) => self.execute_simple(simple, false),
+            Command::Compound(compound, redirects) => {
+                let _saved = match self.apply_redirects(redirects) {
+                    Ok(saved) => saved,
+                    Err(e) => return Ok(self.command_failed(e)),
                 };
-                let is_last = idx + 1 == total_commands;
-                if let Ok(builtin) = Builtin::from_str(&cmd.name) {
-                    let output = match builtin {
-                        Builtin::Cd => builtin::cd(cmd.args),
-                        Builtin::Echo => builtin::echo(cmd.args),
-                        Builtin::History => builtin::history(cmd.args, self.editor.history_mut()),
-                        Builtin::Pwd => builtin::pwd(),
-                        Builtin::Type => builtin::r#type(cmd.args),
-                        Builtin::Declare => builtin::declare(cmd.args, &mut self.variables),
-                        Builtin::Jobs => {
-                            has_job_builtin = true;
-                            builtin::jobs(self.jobs.value())
+                self.execute_compound(compound)
+            }
+            Command::FunctionDefinition { name, body } => {
+                self.functions.insert(name.clone(), body.clone());
+                self.variables.last_status = 0;
+                Ok(Flow::Normal)
+            }
+        }
+    }
+
+    /// Executes a simple command. With `exec`, an external program replaces the shell process.
+    fn execute_simple(&mut self, simple: &SimpleCommand, exec: bool) -> anyhow::Result<Flow> {
+        // Like bash, functions do not inherit the `DEBUG` trap.
+        if !self.variables.in_function()
+            && let Flow::Exit(code) = self.run_trap(Trap::Debug)
+        {
+            return Ok(Flow::Exit(code));
+        }
+        let assignments: Vec<_> = simple.words.iter().map_while(Word::assignment).collect();
+        let (words, declarations) = declaration_words(&simple.words[assignments.len()..]);
+        let expanded = expand_words(&words, &mut self.variables, &self.options);
+        let mut args = match expanded {
+            Ok(args) => args,
+            Err(e) => return Ok(self.command_failed(e)),
+        };
+        // Without a command, the assignments are expanded as they set shell variables.
+        let envs = match args.is_empty() {
+            true => Ok(Vec::new()),
+            false => assignments
+                .iter()
+                .map(|assignment| {
+                    let value = expand_word(&assignment.value, &mut self.variables)?;
+                    Ok((assignment.name.clone(), value))
+                })
+                .collect::<anyhow::Result<Vec<_>>>(),
+        };
+        let envs = match envs {
+            Ok(envs) => envs,
+            Err(e) => return Ok(self.command_failed(e)),
+        };
+        for (name, value) in &envs {
+            self.trace(std::iter::once(format!("{}={}", name, quote_word(value))));
+        }
+        if !args.is_empty() {
+            self.trace(args.iter().map(|arg| quote_word(arg)));
+        }
+        let _saved = match self.apply_redirects(&simple.redirects) {
+            Ok(saved) => saved,
+            Err(e) => return Ok(self.command_failed(e)),
+        };
+        if args.is_empty() {
+            // Assignments without a command set shell variables.
+            self.variables.last_status = match self.assign_all(&assignments) {
+                Ok(()) => 0,
+                Err(e) => return Ok(self.command_failed(e)),
+            };
+            return Ok(Flow::Normal);
+        }
+        let name = args.remove(0);
+        if let Some(body) = self.functions.get(&name).cloned() {
+            return self.call_function(name, &body, args, envs);
+        }
+        if let Ok(builtin) = Builtin::from_str(&name) {
+            // Prefix assignments only last for the builtin.
+            let mut saved = Vec::new();
+            let mut assigned = Ok(());
+            for (name, value) in envs {
+                saved.push((name.clone(), self.variables.variable(&name).cloned()));
+                assigned = self.variables.insert(name, value);
+                if assigned.is_err() {
+                    break;
+                }
+            }
+            // Array values of `declare` and `local` are assigned once the names are declared,
+            // even if they were just made readonly.
+            let readonly: Vec<_> = declarations
+                .iter()
+                .map(|assignment| self.variables.attributes(&assignment.name).readonly)
+                .collect();
+            let result = match assigned {
+                Ok(()) => self.run_builtin(builtin, args),
+                Err(e) => {
+                    eprintln!("{e}");
+                    self.variables.last_status = 1;
+                    Ok(Flow::Normal)
+                }
+            };
+            if result.is_ok()
+                && self.variables.last_status == 0
+                && let Err(e) = self.assign_declarations(&declarations, &readonly)
+            {
+                eprintln!("{e}");
+                self.variables.last_status = 1;
+            }
+            for (name, old) in saved.into_iter().rev() {
+                self.variables.replace(name, old);
+            }
+            return result;
+        }
+        if find_excutable(&name).is_none() {
+            // With `autocd`, an interactive shell changes to a directory given as a command.
+            if self.options.shopt("autocd")
+                && self.variables.is_interactive()
+                && args.is_empty()
+                && Path::new(&name).is_dir()
+            {
+                eprintln!("cd -- {}", name);
+                return self.run_builtin(Builtin::Cd, vec![String::from("--"), name]);
+            }
+            return self.command_not_found(name, args, envs, exec);
+        }
+        let mut command = process::Command::new(&name);
+        command
+            .args(&args)
+            .env_clear()
+            .envs(self.variables.exported())
+            .envs(envs);
+        if exec {
+            let e = command.exec();
+            eprintln!("{}: {}", name, io_error_message(&e));
+            exit_child(126);
+        }
+        self.variables.last_status = match command.spawn() {
+            Ok(child) => wait_pid(child.id())?,
+            Err(e) => {
+                eprintln!("{}: {}", name, io_error_message(&e));
+                126
+            }
+        };
+        Ok(Flow::Normal)
+    }
+
+    /// Runs `command_not_found_handle` with the command and its arguments in a child process if
+    /// it is defined, or reports that the command was not found with status 127.
+    fn command_not_found(
+        &mut self,
+        name: String,
+        args: Vec<String>,
+        envs: Vec<(String, String)>,
+        exec: bool,
+    ) -> anyhow::Result<Flow> {
+        let Some(handler) = self.functions.get(NOT_FOUND_HANDLER).cloned() else {
+            eprintln!("{}: command not found", name);
+            if self.options.shopt("cmdspell")
+                && !name.contains('/')
+                && let Some(suggestion) = self.similar_command(&name)
+            {
+                eprintln!("did you mean `{}`?", suggestion);
+            }
+            self.variables.last_status = 127;
+            return Ok(Flow::Normal);
+        };
+        if !exec {
+            match fork()? {
+                Some(pid) => {
+                    self.variables.last_status = wait_pid(pid)?;
+                    return Ok(Flow::Normal);
+                }
+                None => self.enter_child(),
+            }
+        }
+        // A command the handler itself does not find is reported instead of handled again.
+        self.functions.remove(NOT_FOUND_HANDLER);
+        let args = std::iter::once(name).chain(args).collect();
+        let result = self.call_function(String::from(NOT_FOUND_HANDLER), &handler, args, envs);
+        self.exit_child_with(result)
+    }
+
+    /// The builtin, function, alias or program in `PATH` whose name is closest to `name`.
+    fn similar_command(&self, name: &str) -> Option<String> {
+        let executables = path_executables();
+        let candidates = builtin::NAMES
+            .into_iter()
+            .chain(self.functions.keys().map(String::as_str))
+            .chain(self.helper().aliases.keys().map(String::as_str))
+            .chain(executables.iter().map(String::as_str));
+        similar_command(name, candidates).map(String::from)
+    }
+
+    /// Reports an error that aborts a command. Expanding an unset parameter with `set -u` also
+    /// exits a non-interactive shell.
+    fn command_failed(&mut self, e: anyhow::Error) -> Flow {
+        eprintln!("{e}");
+        self.variables.last_status = 1;
+        match e.is::<Unbound>() && !self.variables.is_interactive() {
+            true => Flow::Exit(1),
+            false => Flow::Normal,
+        }
+    }
+
+    /// Prints a command as it is executed, prefixed with `PS4`, when `set -x` is on.
+    fn trace(&self, words: impl Iterator<Item = String>) {
+        if self.options.option("xtrace") {
+            let prefix = self.variables.parameter("PS4");
+            let words: Vec<String> = words.collect();
+            eprintln!("{}{}", prefix.as_deref().unwrap_or("+ "), words.join(" "));
+        }
+    }
+
+    /// Runs a function body with its own positional parameters and local variables. Prefix
+    /// assignments are local to the call.
+    fn call_function(
+        &mut self,
+        name: String,
+        body: &Command,
+        args: Vec<String>,
+        envs: Vec<(String, String)>,
+    ) -> anyhow::Result<Flow> {
+        self.variables.push_scope(args);
+        self.variables
+            .local(String::from("FUNCNAME"), Some(Value::Scalar(name)))?;
+        for (name, value) in envs {
+            self.variables.local(name, Some(Value::Scalar(value)))?;
+        }
+        // Loops around the call cannot be left from inside the function.
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        let return_trap = self.traps.get(&Trap::Return).cloned();
+        let mut result = self.execute_command(body);
+        self.loop_depth = loop_depth;
+        // Functions do not inherit the `RETURN` trap, so only one set by this call runs.
+        if self.traps.get(&Trap::Return) != return_trap.as_ref()
+            && let Flow::Exit(code) = self.run_trap(Trap::Return)
+        {
+            result = Ok(Flow::Exit(code));
+        }
+        self.variables.pop_scope();
+        match result? {
+            Flow::Return(code) => {
+                self.variables.last_status = code;
+                Ok(Flow::Normal)
+            }
+            Flow::Exit(code) => Ok(Flow::Exit(code)),
+            _ => Ok(Flow::Normal),
+        }
+    }
+
+    fn assign_all(&mut self, assignments: &[Assignment]) -> anyhow::Result<()> {
+        assignments
+            .iter()
+            .try_for_each(|assignment| self.assign(assignment))
+    }
+
+    /// Assigns the array values of `declare` and `local`, lifting the readonly attribute that the
+    /// builtin may have just set, but not one the variable already had.
+    fn assign_declarations(
+        &mut self,
+        declarations: &[Assignment],
+        readonly: &[bool],
+    ) -> anyhow::Result<()> {
+        for (assignment, readonly) in declarations.iter().zip(readonly) {
+            let name = &assignment.name;
+            let attributes = self.variables.attributes(name);
+            if !readonly {
+                let writable = Attributes {
+                    readonly: false,
+                    ..attributes
+                };
+                self.variables.set_attributes(name, writable);
+            }
+            let result = self.assign(assignment);
+            self.variables.set_attributes(name, attributes);
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Assigns `NAME=value`, `NAME+=value`, `NAME[subscript]=value` or `NAME=(...)`.
+    fn assign(&mut self, assignment: &Assignment) -> anyhow::Result<()> {
+        let name = &assignment.name;
+        let operator = if assignment.append { "+=" } else { "=" };
+        if let Some(subscript) = &assignment.subscript {
+            let key = expand_subscript(name, &subscript.text(), &mut self.variables)?;
+            let mut value = expand_word(&assignment.value, &mut self.variables)?;
+            let traced = format!("{}[{}]{}{}", name, key, operator, quote_word(&value));
+            self.trace(std::iter::once(traced));
+            if assignment.append {
+                let old = self.variables.element(name, &key)?.unwrap_or_default();
+                value = self.appended(name, old, value);
+            }
+            return self.variables.set_element(name, &key, value);
+        }
+        if let Some(elements) = assignment.array_elements() {
+            self.trace(std::iter::once(format!(
+                "{}{}({})",
+                name, operator, elements
+            )));
+            let value = self.array_value(name, &elements, assignment.append)?;
+            return self.variables.set(name.clone(), value);
+        }
+        let mut value = expand_word(&assignment.value, &mut self.variables)?;
+        self.trace(std::iter::once(format!(
+            "{}{}{}",
+            name,
+            operator,
+            quote_word(&value)
+        )));
+        if assignment.append
+            && let Some(old) = self.variables.get(name).and_then(Value::scalar)
+        {
+            value = self.appended(name, old.clone(), value);
+        }
+        self.variables.insert(name.clone(), value)
+    }
+
+    /// The value of `NAME+=value`, which adds rather than concatenates for integer variables.
+    fn appended(&self, name: &str, old: String, value: String) -> String {
+        match self.variables.attributes(name).integer {
+            true => format!("{}+({})", old, value),
+            false => old + &value,
+        }
+    }
+
+    /// Builds the array assigned by `NAME=(...)` from the source text of its elements. Elements
+    /// are appended to the existing ones with `+=`.
+    fn array_value(&mut self, name: &str, elements: &str, append: bool) -> anyhow::Result<Value> {
+        let mut words = Vec::new();
+        for token in lexer::tokenize(elements, self.options.shopt("extglob"))? {
+            match token {
+                Token::Word(word) => words.push(word),
+                Token::Newline => {}
+                Token::Operator(operator) => {
+                    anyhow::bail!("syntax error near unexpected token `{}'", operator)
+                }
+                _ => anyhow::bail!("{}: syntax error in array assignment", name),
+            }
+        }
+        let old = match append {
+            true => self.variables.get(name).cloned(),
+            false => None,
+        };
+        if self.variables.is_associative(name) {
+            let mut elements = match old {
+                Some(Value::Associative(elements)) => elements,
+                _ => BTreeMap::new(),
+            };
+            for word in words {
+                let Some((key, value)) = word.keyed_element() else {
+                    anyhow::bail!(
+                        "{}: {}: must use subscript when assigning associative array",
+                        name,
+                        word
+                    )
+                };
+                let key = expand_word(&key, &mut self.variables)?;
+                elements.insert(key, expand_word(&value, &mut self.variables)?);
+            }
+            return Ok(Value::Associative(elements));
+        }
+        let mut elements = match old {
+            Some(Value::Indexed(elements)) => elements,
+            Some(Value::Scalar(value)) => BTreeMap::from([(0, value)]),
+            _ => BTreeMap::new(),
+        };
+        let mut next = elements.keys().last().map_or(0, |last| last + 1);
+        for word in words {
+            if let Some((key, value)) = word.keyed_element() {
+                let index = expand_arithmetic(&key.text(), &mut self.variables)?;
+                let index = usize::try_from(index)
+                    .map_err(|_| anyhow!("{}[{}]: bad array subscript", name, index))?;
+                elements.insert(index, expand_word(&value, &mut self.variables)?);
+                next = index + 1;
+            } else {
+                for value in expand_words(&[word], &mut self.variables, &self.options)? {
+                    elements.insert(next, value);
+                    next += 1;
+                }
+            }
+        }
+        Ok(Value::Indexed(elements))
+    }
+
+    fn run_builtin(&mut self, builtin: Builtin, args: Vec<String>) -> anyhow::Result<Flow> {
+        let output = match builtin {
+            Builtin::Cd => builtin::cd(args, &mut self.variables, &self.options),
+            Builtin::History => builtin::history(args, self.editor.history_mut()),
+            Builtin::Pwd => builtin::pwd(args, &self.variables),
+            Builtin::Pushd => builtin::pushd(args, &mut self.variables),
+            Builtin::Popd => builtin::popd(args, &mut self.variables),
+            Builtin::Dirs => builtin::dirs(args, &mut self.variables),
+            Builtin::Type => builtin::r#type(args, &self.helper().aliases, &self.functions),
+            Builtin::Alias => builtin::alias(args, &mut self.helper_mut().aliases),
+            Builtin::Unalias => builtin::unalias(args, &mut self.helper_mut().aliases),
+            Builtin::Declare | Builtin::Local | Builtin::Readonly => {
+                builtin::declare(&builtin, args, &mut self.variables, &self.functions)
+            }
+            Builtin::Unset => {
+                let functions = &mut self.functions;
+                self.variables.last_status =
+                    match builtin::unset(args, &mut self.variables, functions) {
+                        Ok(status) => status,
+                        Err(e) => {
+                            eprintln!("{e}");
+                            2
                         }
-                        Builtin::Complete => builtin::complete(
-                            cmd.args,
-                            &mut self.editor.helper_mut().unwrap().completers,
-                        ),
-                        Builtin::Exit => return Ok(()),
                     };
-                    command_io = None;
-                    match output {
-                        Ok(std_out) => {
-                            if !std_out.is_empty() {
-                                if let Some(mut file) = cmd.stdout_file {
-                                    writeln!(&mut file, "{}", std_out)?;
-                                } else if !is_last {
-                                    let (stdout_reader, mut stdout_writer) = io::pipe()?;
-                                    command_io = Some(stdout_reader);
-                                    writeln!(stdout_writer, "{}", std_out)?;
-                                } else {
-                                    println!("{}", std_out);
-                                }
-                            }
-                        }
-                        Err(std_err) => {
-                            if let Some(mut file) = cmd.stderr_file {
-                                writeln!(&mut file, "{}", std_err)?;
-                            } else {
-                                println!("{}", std_err);
-                            }
+                return Ok(Flow::Normal);
+            }
+            Builtin::Return if !self.variables.in_function() => Err(anyhow!(
+                "return: can only `return' from a function or sourced script"
+            )),
+            Builtin::Return => match args.first() {
+                Some(code) => match code.parse::<i32>() {
+                    Ok(code) => return Ok(Flow::Return(code & 0xff)),
+                    Err(_) => Err(anyhow!("return: {}: numeric argument required", code)),
+                },
+                None => return Ok(Flow::Return(self.variables.last_status)),
+            },
+            Builtin::Shift => builtin::shift(args, &mut self.variables),
+            Builtin::Trap => {
+                self.variables.last_status = match builtin::trap(args, &mut self.traps) {
+                    Ok(status) => status,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        2
+                    }
+                };
+                return Ok(Flow::Normal);
+            }
+            Builtin::Shopt => {
+                self.variables.last_status = match builtin::shopt(args, &mut self.options) {
+                    Ok(status) => status,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        2
+                    }
+                };
+                return Ok(Flow::Normal);
+            }
+            Builtin::Set => {
+                let variables = &mut self.variables;
+                let output = builtin::set(args, &mut self.options, variables, &self.functions);
+                self.variables.apply_options(&self.options);
+                output
+            }
+            Builtin::True | Builtin::Colon => Ok(String::new()),
+            Builtin::Read => {
+                self.variables.last_status = match builtin::read(args, &mut self.variables) {
+                    Ok(status) => status,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        1
+                    }
+                };
+                return Ok(Flow::Normal);
+            }
+            Builtin::Echo => {
+                let output = builtin::echo(args, &self.options)?;
+                // A closed pipe is not an error worth reporting.
+                let _ = io::stdout().write_all(output.as_bytes());
+                self.variables.last_status = 0;
+                return Ok(Flow::Normal);
+            }
+            Builtin::Printf => {
+                self.variables.last_status = match builtin::printf(args, &mut self.variables) {
+                    Ok(status) => status,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        2
+                    }
+                };
+                return Ok(Flow::Normal);
+            }
+            Builtin::Test | Builtin::Bracket => {
+                self.variables.last_status = match builtin::test(&builtin, args) {
+                    Ok(true) => 0,
+                    Ok(false) => 1,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        2
+                    }
+                };
+                return Ok(Flow::Normal);
+            }
+            Builtin::False => {
+                self.variables.last_status = 1;
+                return Ok(Flow::Normal);
+            }
+            Builtin::Break | Builtin::Continue => {
+                match builtin::loop_count(&builtin, &args, self.loop_depth) {
+                    Ok(n) => {
+                        self.variables.last_status = 0;
+                        return Ok(match builtin {
+                            Builtin::Break => Flow::Break(n),
+                            _ => Flow::Continue(n),
+                        });
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Builtin::Jobs => {
+                self.jobs_listed = true;
+                builtin::jobs(self.jobs.value())
+            }
+            Builtin::Complete => builtin::complete(args, &mut self.helper_mut().completers),
+            Builtin::Exit => match args.first() {
+                Some(code) => match code.parse::<i32>() {
+                    Ok(code) => return Ok(Flow::Exit(code)),
+                    Err(_) => Err(anyhow!("exit: {}: numeric argument required", code)),
+                },
+                None => return Ok(Flow::Exit(self.variables.last_status)),
+            },
+        };
+        match output {
+            Ok(std_out) => {
+                self.variables.last_status = 0;
+                if !std_out.is_empty() {
+                    // A closed pipe is not an error worth reporting.
+                    let _ = writeln!(io::stdout(), "{}", std_out);
+                }
+            }
+            Err(std_err) => {
+                self.variables.last_status = 1;
+                eprintln!("{}", std_err);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn execute_compound(&mut self, compound: &CompoundCommand) -> anyhow::Result<Flow> {
+        match compound {
+            CompoundCommand::Group(list) => self.execute_list(list),
+            CompoundCommand::Subshell(list) => {
+                match fork()? {
+                    Some(pid) => self.variables.last_status = wait_pid(pid)?,
+                    None => {
+                        self.enter_child();
+                        let result = self.execute_list(list);
+                        self.exit_child_with(result)
+                    }
+                }
+                Ok(Flow::Normal)
+            }
+            CompoundCommand::If {
+                branches,
+                else_body,
+            } => {
+                for (condition, body) in branches {
+                    let flow = self.execute_condition(condition)?;
+                    if flow != Flow::Normal {
+                        return Ok(flow);
+                    }
+                    if self.variables.last_status == 0 {
+                        return self.execute_list(body);
+                    }
+                }
+                match else_body {
+                    Some(else_body) => self.execute_list(else_body),
+                    None => {
+                        self.variables.last_status = 0;
+                        Ok(Flow::Normal)
+                    }
+                }
+            }
+            CompoundCommand::While {
+                condition,
+                body,
+                until,
+            } => {
+                self.loop_depth += 1;
+                let result = self.run_while(condition, body, *until);
+                self.loop_depth -= 1;
+                result
+            }
+            CompoundCommand::For {
+                variable,
+                words,
+                body,
+            } => {
+                let words = match words {
+                    Some(words) => match expand_words(words, &mut self.variables, &self.options) {
+                        Ok(words) => words,
+                        Err(e) => return Ok(self.command_failed(e)),
+                    },
+                    None => self.variables.positional().to_vec(),
+                };
+                self.loop_depth += 1;
+                let result = self.run_for(variable, words, body);
+                self.loop_depth -= 1;
+                result
+            }
+            CompoundCommand::ArithmeticFor {
+                init,
+                condition,
+                step,
+                body,
+            } => {
+                self.loop_depth += 1;
+                let result = self.run_arithmetic_for(init, condition, step, body);
+                self.loop_depth -= 1;
+                result
+            }
+            CompoundCommand::Case { word, items } => {
+                let subject = match expand_word(word, &mut self.variables) {
+                    Ok(subject) => subject,
+                    Err(e) => return Ok(self.command_failed(e)),
+                };
+                self.variables.last_status = 0;
+                let mut fall_through = false;
+                for item in items {
+                    let mut matched = fall_through;
+                    for pattern in &item.patterns {
+                        if matched {
+                            break;
                         }
+                        let pattern = expand_pattern(pattern, &mut self.variables)?;
+                        matched =
+                            pattern_matches(&pattern, &subject, self.options.shopt("extglob"));
+                    }
+                    if !matched {
+                        continue;
+                    }
+                    let flow = self.execute_list(&item.body)?;
+                    if flow != Flow::Normal {
+                        return Ok(flow);
+                    }
+                    match item.terminator {
+                        CaseTerminator::Break => break,
+                        CaseTerminator::FallThrough => fall_through = true,
+                        CaseTerminator::Continue => fall_through = false,
                     }
-                } else if find_excutable(&cmd.name).is_none() {
-                    println!("{}: command not found", cmd.name);
-                } else if cmd.is_background_job {
-                    let job_number = self.jobs.new_job_number();
-                    let job = cmd.run_as_background_job(command_io, job_number)?;
-                    println!("[{}] {}", job.number, job.child.id());
-                    self.jobs.push(job);
-                    command_io = None;
-                } else {
-                    match cmd.run_as_excutable(command_io, is_last) {
-                        Ok(output) => command_io = output,
+                }
+                Ok(Flow::Normal)
+            }
+            CompoundCommand::Conditional(expression) => {
+                self.variables.last_status = match self.evaluate_conditional(expression) {
+                    Ok(true) => 0,
+                    Ok(false) => 1,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        2
+                    }
+                };
+                Ok(Flow::Normal)
+            }
+            CompoundCommand::Arithmetic(expression) => {
+                self.variables.last_status =
+                    match expand_arithmetic(expression, &mut self.variables) {
+                        Ok(0) => 1,
+                        Ok(_) => 0,
                         Err(e) => {
-                            println!("{e}");
-                            command_io = None;
+                            eprintln!("{e}");
+                            1
                         }
+                    };
+                Ok(Flow::Normal)
+            }
+        }
+    }
+
+    /// Evaluates a `[[ ]]` expression. Words are not split or globbed, and the right side of
+    /// `==` and `!=` is a pattern.
+    fn evaluate_conditional(&mut self, expression: &Conditional) -> anyhow::Result<bool> {
+        match expression {
+            Conditional::Word(word) => Ok(!expand_word(word, &mut self.variables)?.is_empty()),
+            Conditional::Unary(op, operand) => {
+                unary_test(op, &expand_word(operand, &mut self.variables)?)
+            }
+            Conditional::Binary(lhs, op, rhs) => {
+                let lhs = expand_word(lhs, &mut self.variables)?;
+                match op.as_str() {
+                    "=" | "==" | "!=" => {
+                        let pattern = expand_pattern(rhs, &mut self.variables)?;
+                        let extglob = self.options.shopt("extglob");
+                        Ok(pattern_matches(&pattern, &lhs, extglob) == (op != "!="))
+                    }
+                    "=~" => self.match_regex(&lhs, rhs),
+                    "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+                        let rhs = expand_word(rhs, &mut self.variables)?;
+                        let lhs = arithmetic::evaluate(&lhs, &mut self.variables)?;
+                        let rhs = arithmetic::evaluate(&rhs, &mut self.variables)?;
+                        Ok(integer_test(lhs, op, rhs))
+                    }
+                    _ => {
+                        let rhs = expand_word(rhs, &mut self.variables)?;
+                        Ok(match op.as_str() {
+                            "<" => lhs < rhs,
+                            ">" => lhs > rhs,
+                            _ => file_test(&lhs, op, &rhs),
+                        })
                     }
                 }
             }
-            if !has_job_builtin {
-                self.jobs.print_done();
+            Conditional::Not(expression) => Ok(!self.evaluate_conditional(expression)?),
+            Conditional::And(lhs, rhs) => {
+                Ok(self.evaluate_conditional(lhs)? && self.evaluate_conditional(rhs)?)
+            }
+            Conditional::Or(lhs, rhs) => {
+                Ok(self.evaluate_conditional(lhs)? || self.evaluate_conditional(rhs)?)
+            }
+        }
+    }
+
+    /// Matches `text` against the extended regular expression of `=~`, whose quoted parts match
+    /// literally, and stores the match and its groups in the `BASH_REMATCH` array.
+    fn match_regex(&mut self, text: &str, regex: &Word) -> anyhow::Result<bool> {
+        let pattern: String = expand_pattern(regex, &mut self.variables)?
+            .into_iter()
+            .map(|(c, quoted)| match quoted {
+                true => regex::escape(&c.to_string()),
+                false => c.to_string(),
+            })
+            .collect();
+        let regex = Regex::new(&pattern)
+            .map_err(|_| anyhow!("syntax error in regular expression `{}'", pattern))?;
+        let captures = regex.captures(text);
+        let groups = captures
+            .iter()
+            .flat_map(|captures| captures.iter().enumerate());
+        let matched = groups
+            .map(|(i, group)| (i, group.map_or("", |m| m.as_str()).to_string()))
+            .collect();
+        self.variables
+            .set(String::from("BASH_REMATCH"), Value::Indexed(matched))?;
+        Ok(captures.is_some())
+    }
+
+    fn run_while(&mut self, condition: &List, body: &List, until: bool) -> anyhow::Result<Flow> {
+        let mut status = 0;
+        loop {
+            let flow = self.execute_condition(condition)?;
+            if let Some(flow) = leave_loop(flow) {
+                return Ok(flow);
+            }
+            if (self.variables.last_status == 0) == until {
+                break;
+            }
+            let flow = self.execute_list(body)?;
+            status = self.variables.last_status;
+            if let Some(flow) = leave_loop(flow) {
+                return Ok(flow);
+            }
+        }
+        self.variables.last_status = status;
+        Ok(Flow::Normal)
+    }
+
+    fn run_for(&mut self, variable: &str, words: Vec<String>, body: &List) -> anyhow::Result<Flow> {
+        self.variables.last_status = 0;
+        for word in words {
+            self.variables.insert(variable.to_string(), word)?;
+            let flow = self.execute_list(body)?;
+            if let Some(flow) = leave_loop(flow) {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn run_arithmetic_for(
+        &mut self,
+        init: &str,
+        condition: &str,
+        step: &str,
+        body: &List,
+    ) -> anyhow::Result<Flow> {
+        self.arithmetic(init)?;
+        self.variables.last_status = 0;
+        // An empty condition is always true.
+        while condition.trim().is_empty() || self.arithmetic(condition)? != 0 {
+            let flow = self.execute_list(body)?;
+            if let Some(flow) = leave_loop(flow) {
+                return Ok(flow);
+            }
+            self.arithmetic(step)?;
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn arithmetic(&mut self, expression: &str) -> anyhow::Result<i64> {
+        expand_arithmetic(expression, &mut self.variables)
+    }
+
+    /// Applies redirections, which stay in effect until the returned value is dropped.
+    fn apply_redirects(&mut self, redirects: &[Redirect]) -> anyhow::Result<SavedFds> {
+        let mut saved = SavedFds::new();
+        for redirect in redirects {
+            let mut targets = expand_words(
+                std::slice::from_ref(&redirect.target),
+                &mut self.variables,
+                &self.options,
+            )?;
+            anyhow::ensure!(
+                targets.len() == 1,
+                "{}: ambiguous redirect",
+                redirect.target.text()
+            );
+            let target = targets.remove(0);
+            let open = |options: &mut OpenOptions| {
+                options
+                    .open(&target)
+                    .map_err(|e| anyhow!("{}: {}", target, io_error_message(&e)))
+            };
+            let clobber =
+                redirect.kind == RedirectKind::Clobber || !self.options.option("noclobber");
+            let write = |append: bool| {
+                // `set -C` keeps `>` from truncating an existing regular file, though not `>|`.
+                if !append && !clobber && fs::metadata(&target).is_ok_and(|m| m.is_file()) {
+                    anyhow::bail!("{}: cannot overwrite existing file", target)
+                }
+                open(
+                    File::options()
+                        .write(true)
+                        .create(true)
+                        .append(append)
+                        .truncate(!append),
+                )
+            };
+            match redirect.kind {
+                RedirectKind::Input => {
+                    let file = open(File::options().read(true))?;
+                    saved.redirect(redirect.fd, file.as_raw_fd())?;
+                }
+                RedirectKind::Output | RedirectKind::Clobber => {
+                    saved.redirect(redirect.fd, write(false)?.as_raw_fd())?;
+                }
+                RedirectKind::Append => {
+                    saved.redirect(redirect.fd, write(true)?.as_raw_fd())?;
+                }
+                RedirectKind::OutputAll | RedirectKind::AppendAll => {
+                    let file = write(redirect.kind == RedirectKind::AppendAll)?;
+                    saved.redirect(1, file.as_raw_fd())?;
+                    saved.redirect(2, file.as_raw_fd())?;
+                }
+                RedirectKind::Duplicate => {
+                    if target == "-" {
+                        saved.close(redirect.fd);
+                    } else if let Ok(source) = target.parse::<i32>() {
+                        saved
+                            .redirect(redirect.fd, source)
+                            .map_err(|e| anyhow!("{}: {}", source, io_error_message(&e)))?;
+                    } else if redirect.fd == 1 {
+                        // `>&file` is the same as `&>file`.
+                        let file = write(false)?;
+                        saved.redirect(1, file.as_raw_fd())?;
+                        saved.redirect(2, file.as_raw_fd())?;
+                    } else {
+                        anyhow::bail!("{}: ambiguous redirect", target)
+                    }
+                }
             }
-            self.jobs.clean_up();
         }
+        Ok(saved)
+    }
+}
+
+/// Takes the array assignments `NAME=(...)` out of the arguments of `declare`, `local` and
+/// `readonly`, leaving only their names, so that they can be assigned after the names are
+/// declared.
+fn declaration_words(words: &[Word]) -> (Cow<'_, [Word]>, Vec<Assignment>) {
+    let is_declaration = words
+        .first()
+        .and_then(Word::unquoted_text)
+        .is_some_and(|name| matches!(name.as_str(), "declare" | "local" | "readonly"));
+    if !is_declaration {
+        return (Cow::Borrowed(words), Vec::new());
+    }
+    let mut arrays = Vec::new();
+    let words = words
+        .iter()
+        .map(|word| match word.assignment() {
+            Some(assignment) if assignment.array_elements().is_some() => {
+                let mut name = Word::default();
+                assignment
+                    .name
+                    .chars()
+                    .for_each(|c| name.push(c, Quoting::Unquoted));
+                arrays.push(assignment);
+                name
+            }
+            _ => word.clone(),
+        })
+        .collect();
+    (Cow::Owned(words), arrays)
+}
+
+/// Decides whether the flow out of a loop body leaves the loop, and with which flow.
+fn leave_loop(flow: Flow) -> Option<Flow> {
+    match flow {
+        Flow::Normal | Flow::Continue(1) => None,
+        Flow::Break(1) => Some(Flow::Normal),
+        Flow::Break(n) => Some(Flow::Break(n - 1)),
+        Flow::Continue(n) => Some(Flow::Continue(n - 1)),
+        flow => Some(flow),
     }
 }
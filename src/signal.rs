@@ -0,0 +1,183 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Signals that can be trapped, by name.
+const SIGNALS: [(&str, libc::c_int); 29] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Signals an interactive shell catches so that they only affect its children.
+const INTERACTIVE_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP];
+
+const MAX_SIGNAL: usize = 64;
+
+static PENDING: [AtomicBool; MAX_SIGNAL] = [const { AtomicBool::new(false) }; MAX_SIGNAL];
+static CAUGHT: [AtomicBool; MAX_SIGNAL] = [const { AtomicBool::new(false) }; MAX_SIGNAL];
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// A condition that `trap` can run commands on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trap {
+    Exit,
+    Signal(libc::c_int),
+    Debug,
+    Err,
+    Return,
+}
+
+/// Commands to run on each trapped condition. An empty command ignores the signal.
+pub type Traps = BTreeMap<Trap, String>;
+
+impl Trap {
+    /// Parses a signal specification: a name with or without `SIG`, in any case, or a number.
+    pub fn parse(spec: &str) -> Option<Trap> {
+        if let Ok(number) = spec.parse::<libc::c_int>() {
+            return match number {
+                0 => Some(Trap::Exit),
+                _ => name(number).map(|_| Trap::Signal(number)),
+            };
+        }
+        let spec = spec.to_uppercase();
+        match spec.as_str() {
+            "EXIT" => Some(Trap::Exit),
+            "DEBUG" => Some(Trap::Debug),
+            "ERR" => Some(Trap::Err),
+            "RETURN" => Some(Trap::Return),
+            spec => {
+                let spec = spec.strip_prefix("SIG").unwrap_or(spec);
+                SIGNALS
+                    .iter()
+                    .find(|(name, _)| *name == spec)
+                    .map(|(_, number)| Trap::Signal(*number))
+            }
+        }
+    }
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exit => write!(f, "EXIT"),
+            Self::Signal(number) => write!(f, "SIG{}", name(*number).unwrap_or("UNKNOWN")),
+            Self::Debug => write!(f, "DEBUG"),
+            Self::Err => write!(f, "ERR"),
+            Self::Return => write!(f, "RETURN"),
+        }
+    }
+}
+
+fn name(number: libc::c_int) -> Option<&'static str> {
+    SIGNALS
+        .iter()
+        .find(|(_, n)| *n == number)
+        .map(|(name, _)| *name)
+}
+
+/// The signal names and numbers as `trap -l` lists them, five per line.
+pub fn listing() -> String {
+    let mut signals = SIGNALS.to_vec();
+    signals.sort_by_key(|(_, number)| *number);
+    let entries: Vec<String> = signals
+        .iter()
+        .map(|(name, number)| format!("{:2}) SIG{}", number, name))
+        .collect();
+    let lines: Vec<String> = entries.chunks(5).map(|line| line.join("\t")).collect();
+    lines.join("\n")
+}
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    if let Some(pending) = PENDING.get(signal as usize) {
+        pending.store(true, Ordering::SeqCst);
+    }
+}
+
+fn set_handler(signal: libc::c_int, handler: libc::sighandler_t) {
+    // SAFETY: `on_signal` only stores to atomics, which is async-signal-safe.
+    unsafe { libc::signal(signal, handler) };
+    if let Some(caught) = CAUGHT.get(signal as usize) {
+        caught.store(
+            handler != libc::SIG_IGN && handler != libc::SIG_DFL,
+            Ordering::SeqCst,
+        );
+    }
+}
+
+/// Sets up the shell's own signal handling. An interactive shell is not interrupted, quit or
+/// stopped from the keyboard, while the programs it runs are, since catching a signal does not
+/// carry over to a new program as ignoring it would.
+pub fn init(interactive: bool) {
+    INTERACTIVE.store(interactive, Ordering::SeqCst);
+    if interactive {
+        for signal in INTERACTIVE_SIGNALS {
+            catch(signal);
+        }
+    }
+}
+
+/// Records the signal for `take_pending` instead of its default action.
+pub fn catch(signal: libc::c_int) {
+    let handler: extern "C" fn(libc::c_int) = on_signal;
+    set_handler(signal, handler as libc::sighandler_t);
+}
+
+pub fn ignore(signal: libc::c_int) {
+    set_handler(signal, libc::SIG_IGN);
+}
+
+/// Restores the shell's usual handling of the signal.
+pub fn reset(signal: libc::c_int) {
+    match INTERACTIVE.load(Ordering::SeqCst) && INTERACTIVE_SIGNALS.contains(&signal) {
+        true => catch(signal),
+        false => set_handler(signal, libc::SIG_DFL),
+    }
+}
+
+/// Gives a forked child the default action for every caught signal, keeping ignored ones.
+pub fn reset_in_child() {
+    INTERACTIVE.store(false, Ordering::SeqCst);
+    for (signal, caught) in CAUGHT.iter().enumerate() {
+        PENDING[signal].store(false, Ordering::SeqCst);
+        if caught.load(Ordering::SeqCst) {
+            set_handler(signal as libc::c_int, libc::SIG_DFL);
+        }
+    }
+}
+
+/// Returns the signals received since the last call, in order of number.
+pub fn take_pending() -> Vec<libc::c_int> {
+    (0..MAX_SIGNAL)
+        .filter(|signal| PENDING[*signal].swap(false, Ordering::SeqCst))
+        .map(|signal| signal as libc::c_int)
+        .collect()
+}
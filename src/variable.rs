@@ -0,0 +1,507 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fmt::Display,
+    fs, process,
+};
+
+use crate::{arithmetic, options::Options};
+
+/// The value of a shell variable: a string, or an indexed or associative array.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(String),
+    Indexed(BTreeMap<usize, String>),
+    Associative(BTreeMap<String, String>),
+}
+
+impl Value {
+    /// The value used where a string is expected: element 0 of an array.
+    pub fn scalar(&self) -> Option<&String> {
+        match self {
+            Self::Scalar(value) => Some(value),
+            Self::Indexed(elements) => elements.get(&0),
+            Self::Associative(elements) => elements.get("0"),
+        }
+    }
+
+    /// Values of all elements, in order of their indices or keys.
+    pub fn values(&self) -> Vec<String> {
+        match self {
+            Self::Scalar(value) => vec![value.clone()],
+            Self::Indexed(elements) => elements.values().cloned().collect(),
+            Self::Associative(elements) => elements.values().cloned().collect(),
+        }
+    }
+
+    /// Indices or keys of all elements.
+    pub fn keys(&self) -> Vec<String> {
+        match self {
+            Self::Scalar(_) => vec![String::from("0")],
+            Self::Indexed(elements) => elements.keys().map(usize::to_string).collect(),
+            Self::Associative(elements) => elements.keys().cloned().collect(),
+        }
+    }
+}
+
+/// Attributes of a variable, set and removed with `declare` flags.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Attributes {
+    pub integer: bool,   // -i: assignments are evaluated arithmetically
+    pub lowercase: bool, // -l
+    pub nameref: bool,   // -n: the value names another variable
+    pub readonly: bool,  // -r
+    pub uppercase: bool, // -u
+    pub exported: bool,  // -x: passed in the environment of commands
+}
+
+/// A variable with its attributes. It may be declared without a value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Variable {
+    pub value: Option<Value>,
+    pub attributes: Attributes,
+}
+
+/// Variables shadowed by `local` in a function call, restored when it returns.
+#[derive(Debug, Default)]
+struct Scope {
+    positional: Vec<String>, // the caller's positional parameters
+    shadowed: HashMap<String, Option<Variable>>, // previous variables, `None` when undeclared
+}
+
+/// The error of expanding an unset parameter with `set -u`.
+#[derive(Debug)]
+pub struct Unbound(pub String);
+
+impl Display for Unbound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: unbound variable", self.0)
+    }
+}
+
+impl std::error::Error for Unbound {}
+
+/// Shell variables together with the positional and special parameters.
+#[derive(Debug, Default)]
+pub struct Variables {
+    values: HashMap<String, Variable>,
+    positional: Vec<String>, // $1, $2, ...
+    shell_name: String,      // $0
+    shell_pid: u32,          // $$
+    interactive: bool,
+    option_flags: String, // letters of the `set` options that are on
+    nounset: bool,
+    scopes: Vec<Scope>,                   // one per running function call
+    pub directories: Vec<String>,         // saved by `pushd` below the working directory
+    pub last_status: i32,                 // $?
+    pub last_background_pid: Option<u32>, // $!
+}
+
+impl Variables {
+    /// Creates the variables, exporting those of the environment.
+    pub fn new(shell_name: String, positional: Vec<String>, interactive: bool) -> Self {
+        let exported = Attributes {
+            exported: true,
+            ..Attributes::default()
+        };
+        let values = env::vars()
+            .map(|(name, value)| {
+                let variable = Variable {
+                    value: Some(Value::Scalar(value)),
+                    attributes: exported,
+                };
+                (name, variable)
+            })
+            .collect();
+        let mut variables = Self {
+            values,
+            positional,
+            shell_name,
+            shell_pid: process::id(),
+            interactive,
+            option_flags: String::new(),
+            nounset: false,
+            scopes: Vec::new(),
+            directories: Vec::new(),
+            last_status: 0,
+            last_background_pid: None,
+        };
+        // An inherited `PWD` is only kept if it still names the working directory.
+        if let Some(pwd) = variables.working_directory() {
+            variables.variable_mut("PWD").value = Some(Value::Scalar(pwd));
+            variables.variable_mut("PWD").attributes.exported = true;
+        }
+        variables
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(&self.resolve(name))?.value.as_ref()
+    }
+
+    /// The variable itself, without following a nameref.
+    pub fn variable(&self, name: &str) -> Option<&Variable> {
+        self.values.get(name)
+    }
+
+    /// The variable itself, declaring it when needed, without following a nameref.
+    pub fn variable_mut(&mut self, name: &str) -> &mut Variable {
+        self.values.entry(name.to_string()).or_default()
+    }
+
+    /// Replaces or removes a variable, attributes included, without any checks.
+    pub fn replace(&mut self, name: String, variable: Option<Variable>) {
+        match variable {
+            Some(variable) => self.values.insert(name, variable),
+            None => self.values.remove(&name),
+        };
+    }
+
+    /// All variables, sorted by name.
+    pub fn variables(&self) -> Vec<(&String, &Variable)> {
+        let mut variables: Vec<_> = self.values.iter().collect();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        variables
+    }
+
+    /// Names and values of the exported variables that have a string value.
+    pub fn exported(&self) -> Vec<(String, String)> {
+        self.values
+            .iter()
+            .filter(|(_, variable)| variable.attributes.exported)
+            .filter_map(|(name, variable)| match &variable.value {
+                Some(Value::Scalar(value)) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Attributes of a variable, following a nameref.
+    pub fn attributes(&self, name: &str) -> Attributes {
+        self.values
+            .get(&self.resolve(name))
+            .map(|variable| variable.attributes)
+            .unwrap_or_default()
+    }
+
+    pub fn set_attributes(&mut self, name: &str, attributes: Attributes) {
+        let name = self.resolve(name);
+        self.variable_mut(&name).attributes = attributes;
+    }
+
+    /// Assigns a string to a variable, or to element 0 when it is an array.
+    pub fn insert(&mut self, name: String, value: String) -> anyhow::Result<()> {
+        let name = self.resolve(&name);
+        let value = self.converted(&name, value)?;
+        let variable = self.variable_mut(&name);
+        match &mut variable.value {
+            Some(Value::Indexed(elements)) => {
+                elements.insert(0, value);
+            }
+            Some(Value::Associative(elements)) => {
+                elements.insert(String::from("0"), value);
+            }
+            _ => variable.value = Some(Value::Scalar(value)),
+        }
+        Ok(())
+    }
+
+    /// Replaces the whole value of a variable.
+    pub fn set(&mut self, name: String, value: Value) -> anyhow::Result<()> {
+        let name = self.resolve(&name);
+        self.check_writable(&name)?;
+        self.variable_mut(&name).value = Some(value);
+        Ok(())
+    }
+
+    /// Unsets a variable, or the variable a nameref refers to.
+    pub fn remove(&mut self, name: &str) -> anyhow::Result<Option<Value>> {
+        let name = self.resolve(name);
+        if self.attributes(&name).readonly {
+            anyhow::bail!("{}: cannot unset: readonly variable", name)
+        }
+        Ok(self
+            .values
+            .remove(&name)
+            .and_then(|variable| variable.value))
+    }
+
+    pub fn is_associative(&self, name: &str) -> bool {
+        matches!(self.get(name), Some(Value::Associative(_)))
+    }
+
+    /// Value of an array element. `key` is the key of an associative array, or else an index
+    /// where negative values count back from the end.
+    pub fn element(&self, name: &str, key: &str) -> anyhow::Result<Option<String>> {
+        let value = match self.get(name) {
+            None => None,
+            Some(Value::Associative(elements)) => elements.get(key).cloned(),
+            Some(Value::Indexed(elements)) => match index(name, key, elements.keys().last())? {
+                Some(index) => elements.get(&index).cloned(),
+                None => None,
+            },
+            Some(Value::Scalar(value)) => match index(name, key, Some(&0))? {
+                Some(0) => Some(value.clone()),
+                _ => None,
+            },
+        };
+        Ok(value)
+    }
+
+    /// Assigns to an array element, turning a string or unset variable into an indexed array.
+    pub fn set_element(&mut self, name: &str, key: &str, value: String) -> anyhow::Result<()> {
+        let name = self.resolve(name);
+        let value = self.converted(&name, value)?;
+        let variable = self.variable_mut(&name);
+        if let Some(Value::Associative(elements)) = &mut variable.value {
+            elements.insert(key.to_string(), value);
+            return Ok(());
+        }
+        let mut elements = match variable.value.take() {
+            Some(Value::Indexed(elements)) => elements,
+            Some(Value::Scalar(scalar)) => BTreeMap::from([(0, scalar)]),
+            _ => BTreeMap::new(),
+        };
+        let result = match index(&name, key, elements.keys().last()) {
+            Ok(Some(index)) => {
+                elements.insert(index, value);
+                Ok(())
+            }
+            Ok(None) => Err(anyhow::anyhow!("{}[{}]: bad array subscript", name, key)),
+            Err(e) => Err(e),
+        };
+        variable.value = Some(Value::Indexed(elements));
+        result
+    }
+
+    /// Removes an array element. Removing element 0 of a string unsets it.
+    pub fn remove_element(&mut self, name: &str, key: &str) -> anyhow::Result<()> {
+        let name = self.resolve(name);
+        if self.attributes(&name).readonly {
+            anyhow::bail!("{}: cannot unset: readonly variable", name)
+        }
+        let Some(variable) = self.values.get_mut(&name) else {
+            return Ok(());
+        };
+        match &mut variable.value {
+            None => {}
+            Some(Value::Associative(elements)) => {
+                elements.remove(key);
+            }
+            Some(Value::Indexed(elements)) => {
+                match index(&name, key, elements.keys().last())? {
+                    Some(index) => elements.remove(&index),
+                    None => anyhow::bail!("{}[{}]: bad array subscript", name, key),
+                };
+            }
+            Some(Value::Scalar(_)) if index(&name, key, Some(&0))? == Some(0) => {
+                self.values.remove(&name);
+            }
+            Some(Value::Scalar(_)) => {}
+        }
+        Ok(())
+    }
+
+    /// The variable a nameref refers to, or `name` itself.
+    fn resolve(&self, name: &str) -> String {
+        let mut name = name.to_string();
+        // Bounded, since namerefs may form a cycle.
+        for _ in 0..8 {
+            match self.values.get(&name) {
+                Some(Variable {
+                    value: Some(Value::Scalar(target)),
+                    attributes,
+                }) if attributes.nameref && !target.is_empty() => name = target.clone(),
+                _ => break,
+            }
+        }
+        name
+    }
+
+    fn check_writable(&self, name: &str) -> anyhow::Result<()> {
+        match self.attributes(name).readonly {
+            true => anyhow::bail!("{}: readonly variable", name),
+            false => Ok(()),
+        }
+    }
+
+    /// A value about to be assigned, evaluated for integer variables and case-converted.
+    fn converted(&mut self, name: &str, value: String) -> anyhow::Result<String> {
+        self.check_writable(name)?;
+        let attributes = self.attributes(name);
+        let value = match attributes.integer {
+            true => arithmetic::evaluate(&value, self)?.to_string(),
+            false => value,
+        };
+        Ok(match (attributes.lowercase, attributes.uppercase) {
+            (true, false) => value.to_lowercase(),
+            (false, true) => value.to_uppercase(),
+            _ => value,
+        })
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    pub fn shift(&mut self, n: usize) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            n <= self.positional.len(),
+            "shift: {}: shift count out of range",
+            n
+        );
+        self.positional.drain(..n);
+        Ok(())
+    }
+
+    /// Enters a function call with its own positional parameters.
+    pub fn push_scope(&mut self, positional: Vec<String>) {
+        let positional = std::mem::replace(&mut self.positional, positional);
+        self.scopes.push(Scope {
+            positional,
+            shadowed: HashMap::new(),
+        });
+    }
+
+    /// Leaves a function call, restoring the caller's positional parameters and variables.
+    pub fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        self.positional = scope.positional;
+        for (name, variable) in scope.shadowed {
+            self.replace(name, variable);
+        }
+    }
+
+    pub fn in_function(&self) -> bool {
+        !self.scopes.is_empty()
+    }
+
+    /// Makes a variable local to the current function call. Without a value it is declared but
+    /// unset, or keeps its value when it is already local.
+    pub fn local(&mut self, name: String, value: Option<Value>) -> anyhow::Result<()> {
+        let Some(scope) = self.scopes.last() else {
+            anyhow::bail!("local: can only be used in a function")
+        };
+        if scope.shadowed.contains_key(&name) {
+            if let Some(value) = value {
+                self.set(name, value)?;
+            }
+            return Ok(());
+        }
+        if self
+            .values
+            .get(&name)
+            .is_some_and(|v| v.attributes.readonly)
+        {
+            anyhow::bail!("{}: readonly variable", name)
+        }
+        let old = self.values.get(&name).cloned();
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.shadowed.insert(name.clone(), old);
+        }
+        let variable = Variable {
+            value,
+            attributes: Attributes::default(),
+        };
+        self.values.insert(name, variable);
+        Ok(())
+    }
+
+    /// Field separators from `IFS`, defaulting to space, tab and newline when unset.
+    pub fn ifs(&self) -> String {
+        self.parameter("IFS")
+            .unwrap_or_else(|| String::from(" \t\n"))
+    }
+
+    /// The working directory as `$PWD` names it, keeping the symlinks `cd` followed, unless it
+    /// no longer names the working directory.
+    pub fn working_directory(&self) -> Option<String> {
+        let Ok(physical) = env::current_dir() else {
+            return self.parameter("PWD");
+        };
+        match self.parameter("PWD") {
+            Some(pwd)
+                if pwd.starts_with('/') && fs::canonicalize(&pwd).is_ok_and(|p| p == physical) =>
+            {
+                Some(pwd)
+            }
+            _ => Some(physical.display().to_string()),
+        }
+    }
+
+    /// The stack of `pushd`, `popd` and `dirs`: the working directory, then the saved ones.
+    pub fn directory_stack(&self) -> Vec<String> {
+        let pwd = self.working_directory().unwrap_or_default();
+        std::iter::once(pwd)
+            .chain(self.directories.iter().cloned())
+            .collect()
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+
+    /// Takes note of the `set` options that parameters depend on.
+    pub fn apply_options(&mut self, options: &Options) {
+        self.option_flags = options.flags();
+        self.nounset = options.option("nounset");
+    }
+
+    /// Whether expanding an unset parameter is an error, as with `set -u`.
+    pub fn is_nounset(&self) -> bool {
+        self.nounset
+    }
+
+    /// Current option flags, as reported by `$-`.
+    pub fn flags(&self) -> String {
+        let mut flags = self.option_flags.clone();
+        if self.interactive {
+            flags.push('i');
+        }
+        flags
+    }
+
+    /// Replaces the positional parameters, as with `set -- ARGS`.
+    pub fn set_positional(&mut self, positional: Vec<String>) {
+        self.positional = positional;
+    }
+
+    /// Value of a named variable, a positional parameter or a special parameter.
+    pub fn parameter(&self, name: &str) -> Option<String> {
+        match name {
+            "$" => Some(self.shell_pid.to_string()),
+            "!" => self.last_background_pid.map(|pid| pid.to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "?" => Some(self.last_status.to_string()),
+            "-" => Some(self.flags()),
+            "0" => Some(self.shell_name.clone()),
+            "@" => Some(self.positional.join(" ")),
+            "*" => {
+                let separator = self.ifs().chars().next().map(String::from);
+                Some(self.positional.join(separator.as_deref().unwrap_or("")))
+            }
+            _ if name.chars().all(|c| c.is_ascii_digit()) => name
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .and_then(|n| self.positional.get(n - 1))
+                .cloned(),
+            _ => self.get(name).and_then(Value::scalar).cloned(),
+        }
+    }
+}
+
+/// Resolves an array index, counting negative indices back from `last`. Returns `None` when a
+/// negative index reaches before the start.
+fn index(name: &str, key: &str, last: Option<&usize>) -> anyhow::Result<Option<usize>> {
+    let index: i64 = key
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("{}[{}]: bad array subscript", name, key))?;
+    if index >= 0 {
+        return Ok(Some(index as usize));
+    }
+    let len = last.map_or(0, |last| *last as i64 + 1);
+    Ok(usize::try_from(len + index).ok())
+}